@@ -0,0 +1,66 @@
+use std::{fmt::Display, time::Duration};
+
+use chb_chess::Color;
+use serde::{Deserialize, Serialize};
+
+/// Time added to a player's clock for each move. All values are in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bonus {
+    #[default]
+    None,
+    /// Fischer increment: always added after the move
+    Increment(u64),
+    /// Bronstein delay: the time used is given back, up to the delay
+    Bronstein(u64),
+    /// Simple delay: the clock only starts running once the delay has passed
+    Delay(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    /// Starting time for each side in milliseconds
+    pub base: u64,
+    #[serde(default)]
+    pub bonus: Bonus,
+}
+
+impl TimeControl {
+    pub fn base(&self) -> Duration {
+        Duration::from_millis(self.base)
+    }
+}
+
+/// Short form like "5+3", with the base in minutes and the bonus in seconds
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.base % 60_000 == 0 {
+            write!(f, "{}", self.base / 60_000)?;
+        } else {
+            write!(f, "{}s", self.base / 1000)?;
+        }
+        match self.bonus {
+            Bonus::None => Ok(()),
+            Bonus::Increment(ms) => write!(f, "+{}", ms / 1000),
+            Bonus::Bronstein(ms) => write!(f, " bronstein {}", ms / 1000),
+            Bonus::Delay(ms) => write!(f, " delay {}", ms / 1000),
+        }
+    }
+}
+
+/// Remaining time for each side in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockState {
+    pub white: u64,
+    pub black: u64,
+    /// The side whose clock is running, if any
+    pub running: Option<Color>,
+}
+
+impl ClockState {
+    pub fn remaining(&self, color: Color) -> Duration {
+        Duration::from_millis(match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        })
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use chb_chess::Color;
+use serde::{Deserialize, Serialize};
+
+/// A computer opponent to seat when creating a board
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ComputerSeat {
+    pub color: Color,
+    pub player: ComputerPlayer,
+}
+
+/// Strongest level accepted for [`ComputerPlayer::Bot`]
+pub const MAX_BOT_LEVEL: u8 = 5;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ComputerPlayer {
+    /// The UCI engine configured on the server
+    Uci(EngineSettings),
+    /// The built-in bot, from level 1 (random moves) to [`MAX_BOT_LEVEL`]
+    Bot { level: u8 },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EngineSettings {
+    pub limit: SearchLimit,
+    /// Passed to the engine with `setoption`
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+/// How long the engine searches for each move
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum SearchLimit {
+    Depth(u32),
+    /// Milliseconds
+    MoveTime(u64),
+    Nodes(u64),
+}
@@ -0,0 +1,30 @@
+use chb_chess::BoardBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::TimeControl, computer::ComputerSeat, start::StartPosition};
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct CreateBoard {
+    #[serde(default)]
+    pub builder: Option<BoardBuilder>,
+    /// Used when there's no `builder`. The standard position if neither is given.
+    #[serde(default)]
+    pub start: Option<StartPosition>,
+    /// Untimed if `None`
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    /// Seats a computer player straight away
+    #[serde(default)]
+    pub computer: Option<ComputerSeat>,
+    /// Counts towards ratings. Both players must be registered.
+    #[serde(default)]
+    pub rated: bool,
+}
+
+/// Query for importing a PGN
+#[derive(Default, Serialize, Deserialize)]
+pub struct ImportPgn {
+    /// Number of half moves to replay before play continues. All of them if `None`.
+    #[serde(default)]
+    pub ply: Option<usize>,
+}
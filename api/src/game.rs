@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use chb_chess::{Color, Move};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::ClockState, position::Position};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+    Timeout,
+    /// Flag fell, but the opponent could not have delivered checkmate
+    TimeoutVsInsufficientMaterial,
+    /// A player left and didn't come back in time
+    Abandoned,
+    Resignation,
+    /// Both players agreed to a draw
+    Agreement,
+    /// Called off before it really started. Nobody wins or loses.
+    Aborted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameResult {
+    /// `None` if the game was drawn
+    pub winner: Option<Color>,
+    pub reason: Termination,
+}
+
+impl GameResult {
+    pub fn win(winner: Color, reason: Termination) -> Self {
+        Self {
+            winner: Some(winner),
+            reason,
+        }
+    }
+
+    pub fn draw(reason: Termination) -> Self {
+        Self {
+            winner: None,
+            reason,
+        }
+    }
+
+    pub fn aborted() -> Self {
+        Self {
+            winner: None,
+            reason: Termination::Aborted,
+        }
+    }
+
+    /// The score as written in PGN, e.g. `1-0`
+    pub fn score(&self) -> &'static str {
+        match (self.winner, self.reason) {
+            (_, Termination::Aborted) => "*",
+            (Some(Color::White), _) => "1-0",
+            (Some(Color::Black), _) => "0-1",
+            (None, _) => "1/2-1/2",
+        }
+    }
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::ThreefoldRepetition => "threefold repetition",
+            Termination::FiftyMoveRule => "fifty-move rule",
+            Termination::InsufficientMaterial => "insufficient material",
+            Termination::Timeout => "timeout",
+            Termination::TimeoutVsInsufficientMaterial => "timeout vs insufficient material",
+            Termination::Abandoned => "abandoned",
+            Termination::Resignation => "resignation",
+            Termination::Agreement => "agreement",
+            Termination::Aborted => "aborted",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.score(), self.reason)
+    }
+}
+
+/// Things a player can do besides moving
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameAction {
+    Resign,
+    /// Accepts instead if the opponent has already offered
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Asks to undo the requester's last move, along with any reply to it
+    RequestTakeback,
+    AcceptTakeback,
+    DeclineTakeback,
+    /// Only allowed before the first move
+    Abort,
+}
+
+/// Offers waiting on an answer, by who made them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offers {
+    pub draw: Option<Color>,
+    pub takeback: Option<Color>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameInfo {
+    pub board: Position,
+    pub result: Option<GameResult>,
+    pub clock: Option<ClockState>,
+}
+
+/// A move that has been played, as listed in the game's history
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub mv: Move,
+    pub san: String,
+    /// The clocks just after the move was made
+    pub clock: Option<ClockState>,
+}
+
+/// Everything needed to rebuild a game from its start
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct History {
+    pub start_fen: String,
+    pub moves: Vec<HistoryEntry>,
+}
@@ -6,3 +6,19 @@ pub struct JoinBoard {
     pub id: String,
     pub play_as: Color,
 }
+
+/// Query for joining a board
+#[derive(Default, Serialize, Deserialize)]
+pub struct JoinQuery {
+    /// Token from an earlier [`SeatGrant`], needed to get back into a taken seat
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Sent to a player when they are seated. Keep the token to reconnect later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeatGrant {
+    pub id: String,
+    pub color: Color,
+    pub token: String,
+}
@@ -1 +1,12 @@
+pub mod clock;
+pub mod computer;
+pub mod create;
+pub mod game;
 pub mod join;
+pub mod listing;
+pub mod position;
+pub mod protocol;
+pub mod rating;
+pub mod seek;
+pub mod start;
+pub mod user;
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::TimeControl, game::GameResult, rating::Category};
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStatus {
+    /// Waiting for players
+    Setup,
+    Active,
+    Finished,
+}
+
+/// Filters for listing boards. Anything left out matches every board.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ListBoards {
+    #[serde(default)]
+    pub status: Option<GameStatus>,
+    #[serde(default)]
+    pub category: Option<Category>,
+    #[serde(default)]
+    pub rated: Option<bool>,
+    /// Starts at 0
+    #[serde(default)]
+    pub page: usize,
+    /// Defaults to [`DEFAULT_PAGE_SIZE`], and can't be more than [`MAX_PAGE_SIZE`]
+    #[serde(default)]
+    pub per_page: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BoardSummary {
+    pub id: String,
+    pub status: GameStatus,
+    /// White first
+    pub names: [Option<String>; 2],
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub fen: String,
+    pub result: Option<GameResult>,
+    /// Unix timestamp in seconds
+    pub created_at: i64,
+}
+
+/// Newest boards first
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BoardPage {
+    pub boards: Vec<BoardSummary>,
+    /// Number of boards matching the filters, over all pages
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
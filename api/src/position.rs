@@ -0,0 +1,527 @@
+use std::{fmt::Display, ops::Index, str::FromStr};
+
+use chb_chess::{Board, Color, Move, Piece, PieceKind, Square};
+use serde::{Deserialize, Serialize};
+
+const QUEENSIDE: usize = 0;
+const KINGSIDE: usize = 1;
+/// Ranks the kings start on, White's then Black's
+const BACK_RANKS: [usize; 2] = [0, 7];
+
+/// A [`Board`] that can also castle from Chess960 starts.
+///
+/// `Board` only castles with the king on the e-file and the rooks in the corners. Positions with
+/// castling rights for any other king or rook keep those rights here instead, by the files of
+/// the rooks, and castle here: the king takes its own rook, as in UCI's Chess960 mode, and lands
+/// on the g- or c-file with the rook next to it on the f- or d-file. Their FENs give the rights
+/// by file, like `HAha`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Position {
+    board: Board,
+    /// Files of the rooks each side can still castle with, queenside then kingside. `None` when
+    /// `board` does the castling itself.
+    rooks: Option<[[Option<usize>; 2]; 2]>,
+}
+
+/// Why a FEN or move was refused by a [`Position`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    InvalidFen,
+    IllegalMove,
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            PositionError::InvalidFen => "invalid FEN",
+            PositionError::IllegalMove => "illegal move",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+impl Position {
+    /// Legal moves, with Chess960 castling as the king taking its own rook
+    pub fn moves(&self) -> Vec<Move> {
+        let mut moves = self.board.moves();
+        if self.rooks.is_some() {
+            let castling = [QUEENSIDE, KINGSIDE]
+                .into_iter()
+                .filter_map(|side| self.castle(side).map(|(mv, _)| mv));
+            moves.extend(castling);
+        }
+        moves
+    }
+
+    pub fn make(&mut self, mv: Move) -> Result<(), PositionError> {
+        let Some(mut rooks) = self.rooks else {
+            return self
+                .board
+                .make(mv)
+                .map(|_| ())
+                .map_err(|_| PositionError::IllegalMove);
+        };
+        let color = self.board.color_to_move();
+        let own = index(color);
+        let king = Piece::Filled(PieceKind::King, color);
+        if self.board[mv.origin] == king {
+            let side = [QUEENSIDE, KINGSIDE].into_iter().find(|&side| {
+                rooks[own][side].is_some_and(|file| coords(mv.dest) == (file, BACK_RANKS[own]))
+            });
+            if let Some(side) = side {
+                return match self.castle(side) {
+                    Some((castling, castled)) if castling.origin == mv.origin => {
+                        *self = castled;
+                        Ok(())
+                    }
+                    _ => Err(PositionError::IllegalMove),
+                };
+            }
+            rooks[own] = [None; 2];
+        }
+        self.board
+            .make(mv)
+            .map_err(|_| PositionError::IllegalMove)?;
+        // Rooks that move or are taken can't castle any more
+        let touched = [mv.origin, mv.dest].map(coords);
+        for (sides, rank) in rooks.iter_mut().zip(BACK_RANKS) {
+            for rook in sides {
+                if rook.is_some_and(|file| touched.contains(&(file, rank))) {
+                    *rook = None;
+                }
+            }
+        }
+        self.rooks = Some(rooks);
+        Ok(())
+    }
+
+    pub fn to_fen(&self) -> String {
+        let fen = self.board.to_fen();
+        let Some(rooks) = &self.rooks else {
+            return fen;
+        };
+        let castling = castling_field(rooks, false);
+        fen.split_whitespace()
+            .enumerate()
+            .map(|(i, field)| if i == 2 { castling.as_str() } else { field })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn color_to_move(&self) -> Color {
+        self.board.color_to_move()
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.board.in_check()
+    }
+
+    /// Whether castling is done the Chess960 way, by the king taking its own rook
+    pub fn is_chess960(&self) -> bool {
+        self.rooks.is_some()
+    }
+
+    /// The move castling on `side` and the position after it, if the side to move can castle
+    /// there
+    fn castle(&self, side: usize) -> Option<(Move, Position)> {
+        let mut rooks = self.rooks?;
+        let color = self.board.color_to_move();
+        let own = index(color);
+        let rank = BACK_RANKS[own];
+        let rook = rooks[own][side]?;
+        let fen = self.board.to_fen();
+        let fields = fen.split_whitespace().collect::<Vec<_>>();
+        let (placement, to_move) = (*fields.first()?, *fields.get(1)?);
+        let grid = read_placement(placement)?;
+        let king = king_file(&grid, own)?;
+        let (king_to, rook_to) = [(2, 3), (6, 5)][side];
+
+        // Everything the king and rook cross or land on must be empty but for the two of them
+        let crossed = |from: usize, to: usize| from.min(to)..=from.max(to);
+        let blocked = crossed(king, king_to)
+            .chain(crossed(rook, rook_to))
+            .any(|file| file != king && file != rook && grid[rank][file].is_some());
+        if blocked || self.board.in_check() {
+            return None;
+        }
+        // Nor can the king cross or land on an attacked square
+        let (king_piece, rook_piece) = (grid[rank][king], grid[rank][rook]);
+        let crosses_attack = crossed(king, king_to)
+            .filter(|&file| file != king && file != king_to)
+            .any(|file| {
+                let mut passing = grid;
+                passing[rank][king] = None;
+                passing[rank][file] = king_piece;
+                in_check(&passing, to_move)
+            });
+        let mut castled = grid;
+        castled[rank][king] = None;
+        castled[rank][rook] = None;
+        castled[rank][king_to] = king_piece;
+        castled[rank][rook_to] = rook_piece;
+        if crosses_attack || in_check(&castled, to_move) {
+            return None;
+        }
+
+        let halfmove = fields
+            .get(4)
+            .and_then(|h| h.parse::<u32>().ok())
+            .unwrap_or(0);
+        let fullmove = fields
+            .get(5)
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(1);
+        let (next, fullmove) = match color {
+            Color::White => ("b", fullmove),
+            Color::Black => ("w", fullmove + 1),
+        };
+        let placement = write_placement(&castled);
+        let board = format!("{placement} {next} - - {} {fullmove}", halfmove + 1)
+            .parse::<Board>()
+            .ok()?;
+        let mv = format!("{}{}", square_name(king, rank), square_name(rook, rank))
+            .parse::<Move>()
+            .ok()?;
+        rooks[own] = [None; 2];
+        Some((
+            mv,
+            Position {
+                board,
+                rooks: Some(rooks),
+            },
+        ))
+    }
+}
+
+impl FromStr for Position {
+    type Err = PositionError;
+
+    /// Reads castling rights as `KQkq`, with the outermost rooks, or by file as in `HAha`
+    fn from_str(fen: &str) -> Result<Self, Self::Err> {
+        let mut fields = fen.split_whitespace().collect::<Vec<_>>();
+        let (Some(placement), Some(castling)) = (fields.first(), fields.get(2)) else {
+            return Err(PositionError::InvalidFen);
+        };
+        let grid = read_placement(placement).ok_or(PositionError::InvalidFen)?;
+        let rooks = read_castling(&grid, castling).ok_or(PositionError::InvalidFen)?;
+        let standard = (0..2).all(|color| {
+            (0..2).all(|side| {
+                rooks[color][side].is_none()
+                    || (king_file(&grid, color) == Some(4) && rooks[color][side] == Some(side * 7))
+            })
+        });
+        let castling = if standard {
+            castling_field(&rooks, true)
+        } else {
+            "-".to_owned()
+        };
+        fields[2] = &castling;
+        let board = fields
+            .join(" ")
+            .parse::<Board>()
+            .map_err(|_| PositionError::InvalidFen)?;
+        Ok(Position {
+            board,
+            rooks: (!standard).then_some(rooks),
+        })
+    }
+}
+
+impl From<Board> for Position {
+    fn from(board: Board) -> Self {
+        Position { board, rooks: None }
+    }
+}
+
+impl TryFrom<String> for Position {
+    type Error = PositionError;
+
+    fn try_from(fen: String) -> Result<Self, Self::Error> {
+        fen.parse()
+    }
+}
+
+impl From<Position> for String {
+    fn from(position: Position) -> Self {
+        position.to_fen()
+    }
+}
+
+impl Index<Square> for Position {
+    type Output = Piece;
+
+    fn index(&self, square: Square) -> &Piece {
+        &self.board[square]
+    }
+}
+
+/// Pieces by rank, then file, as their FEN letters
+type Grid = [[Option<char>; 8]; 8];
+
+fn read_placement(placement: &str) -> Option<Grid> {
+    let mut grid = [[None; 8]; 8];
+    let rows = placement.split('/').collect::<Vec<_>>();
+    if rows.len() != 8 {
+        return None;
+    }
+    // FENs start from the eighth rank
+    for (row, rank) in rows.into_iter().zip(grid.iter_mut().rev()) {
+        let mut file = 0;
+        for c in row.chars() {
+            match c.to_digit(10) {
+                Some(empty) => file += empty as usize,
+                None => {
+                    *rank.get_mut(file)? = Some(c);
+                    file += 1;
+                }
+            }
+        }
+        if file != 8 {
+            return None;
+        }
+    }
+    Some(grid)
+}
+
+fn write_placement(grid: &Grid) -> String {
+    let rows = grid.iter().rev().map(|rank| {
+        let mut row = String::new();
+        let mut empty = 0;
+        for square in rank {
+            match square {
+                Some(c) => {
+                    if empty > 0 {
+                        row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    row.push(*c);
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        row
+    });
+    rows.collect::<Vec<_>>().join("/")
+}
+
+/// Rook files for the castling rights in `castling`, which may use either form
+fn read_castling(grid: &Grid, castling: &str) -> Option<[[Option<usize>; 2]; 2]> {
+    let mut rooks = [[None; 2]; 2];
+    if castling == "-" {
+        return Some(rooks);
+    }
+    for c in castling.chars() {
+        let color = usize::from(c.is_ascii_lowercase());
+        let rank = BACK_RANKS[color];
+        let rook = if color == 0 { 'R' } else { 'r' };
+        let is_rook = |file: &usize| grid[rank][*file] == Some(rook);
+        let king = king_file(grid, color)?;
+        let file = match c.to_ascii_lowercase() {
+            'k' => (king + 1..8).rev().find(is_rook)?,
+            'q' => (0..king).find(is_rook)?,
+            letter @ 'a'..='h' => Some(letter as usize - 'a' as usize).filter(is_rook)?,
+            _ => return None,
+        };
+        let side = if file > king { KINGSIDE } else { QUEENSIDE };
+        rooks[color][side] = Some(file);
+    }
+    Some(rooks)
+}
+
+/// Castling rights as `KQkq`, or by file if not `standard`
+fn castling_field(rooks: &[[Option<usize>; 2]; 2], standard: bool) -> String {
+    let mut castling = String::new();
+    for (color, sides) in rooks.iter().enumerate() {
+        for side in [KINGSIDE, QUEENSIDE] {
+            let Some(file) = sides[side] else {
+                continue;
+            };
+            let c = if standard {
+                ['Q', 'K'][side]
+            } else {
+                char::from(b'A' + file as u8)
+            };
+            castling.push(if color == 0 {
+                c
+            } else {
+                c.to_ascii_lowercase()
+            });
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+    castling
+}
+
+fn king_file(grid: &Grid, color: usize) -> Option<usize> {
+    let king = if color == 0 { 'K' } else { 'k' };
+    grid[BACK_RANKS[color]]
+        .iter()
+        .position(|p| *p == Some(king))
+}
+
+/// Whether the side to move in `grid` is in check, counting boards that can't be read as check
+fn in_check(grid: &Grid, to_move: &str) -> bool {
+    format!("{} {to_move} - - 0 1", write_placement(grid))
+        .parse::<Board>()
+        .map_or(true, |board| board.in_check())
+}
+
+fn index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// File and rank of `square`, counting from zero
+fn coords(square: Square) -> (usize, usize) {
+    let name = square.to_string();
+    let [file, rank] = [name.as_bytes()[0] - b'a', name.as_bytes()[1] - b'1'];
+    (usize::from(file), usize::from(rank))
+}
+
+fn square_name(file: usize, rank: usize) -> String {
+    format!("{}{}", char::from(b'a' + file as u8), rank + 1)
+}
+
+/// Identifies a position for repetition and sync purposes: piece placement, side to move,
+/// castling rights and en passant square. The move counters are dropped from the FEN.
+pub fn position_key(fen: &str) -> String {
+    fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+}
+
+/// Hash of the position key, which is the same on the server and in the browser
+pub fn position_hash(position: &Position) -> u64 {
+    // FNV-1a, since `std`'s hasher isn't guaranteed to be stable between builds
+    position_key(&position.to_fen())
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// A move made on the server, with enough to tell whether the receiver is still in sync
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct MoveUpdate {
+    pub mv: Move,
+    /// Number of moves made in the game, including this one
+    pub ply: usize,
+    /// [`position_hash`] of the position after the move
+    pub hash: u64,
+}
+
+/// The authoritative position, sent when a client needs to resync
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub fen: String,
+    pub ply: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `uci` in `fen`, returning the FEN after it
+    fn play(fen: &str, uci: &str) -> Result<String, PositionError> {
+        let mut position = fen.parse::<Position>()?;
+        position.make(uci.parse().unwrap())?;
+        Ok(position.to_fen())
+    }
+
+    fn castling_moves(fen: &str) -> Vec<String> {
+        let position = fen.parse::<Position>().unwrap();
+        let color = position.color_to_move();
+        let (king, rook) = (
+            Piece::Filled(PieceKind::King, color),
+            Piece::Filled(PieceKind::Rook, color),
+        );
+        let mut moves = position
+            .moves()
+            .into_iter()
+            .filter(|mv| position[mv.origin] == king && position[mv.dest] == rook)
+            .map(|mv| mv.to_string())
+            .collect::<Vec<_>>();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn standard_positions_are_left_to_the_board() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let position = fen.parse::<Position>().unwrap();
+        assert!(!position.is_chess960());
+        assert_eq!(position.to_fen(), fen);
+        // Rights by file are read the same way when they name the corners
+        let shredder = "r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1".parse::<Position>();
+        assert_eq!(shredder.unwrap().to_fen(), fen);
+        assert_eq!(
+            play(fen, "e1g1").unwrap(),
+            "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1"
+        );
+    }
+
+    #[test]
+    fn king_takes_its_rook_to_castle() {
+        let fen = "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1";
+        assert_eq!(castling_moves(fen), ["g1b1", "g1h1"]);
+        assert_eq!(
+            play(fen, "g1h1").unwrap(),
+            "1r4kr/8/8/8/8/8/8/1R3RK1 b hb - 1 1"
+        );
+        assert_eq!(
+            play(fen, "g1b1").unwrap(),
+            "1r4kr/8/8/8/8/8/8/2KR3R b hb - 1 1"
+        );
+    }
+
+    #[test]
+    fn king_and_rook_can_swap_sides() {
+        assert_eq!(
+            play("4k3/8/8/8/8/8/8/RK6 w A - 0 1", "b1a1").unwrap(),
+            "4k3/8/8/8/8/8/8/2KR4 b - - 1 1"
+        );
+    }
+
+    #[test]
+    fn castling_needs_a_clear_and_safe_path() {
+        // A knight in the way on the queenside
+        assert_eq!(castling_moves("4k3/8/8/8/8/8/8/RN4KR w HA - 0 1"), ["g1h1"]);
+        // The queenside path crosses e1, which the rook attacks
+        assert_eq!(
+            castling_moves("4r1k1/8/8/8/8/8/8/R5KR w HA - 0 1"),
+            ["g1h1"]
+        );
+        // Not out of check
+        assert!(castling_moves("6r1/7k/8/8/8/8/8/R5KR w HA - 0 1").is_empty());
+        assert_eq!(
+            play("6r1/7k/8/8/8/8/8/R5KR w HA - 0 1", "g1h1"),
+            Err(PositionError::IllegalMove)
+        );
+    }
+
+    #[test]
+    fn moving_the_king_or_a_rook_gives_up_castling() {
+        let fen = "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1";
+        assert_eq!(
+            play(fen, "h1h2").unwrap(),
+            "1r4kr/8/8/8/8/8/7R/1R4K1 b Bhb - 1 1"
+        );
+        assert_eq!(
+            play(fen, "g1f1").unwrap(),
+            "1r4kr/8/8/8/8/8/8/1R3K1R b hb - 1 1"
+        );
+        // Taking a rook takes its castling with it
+        assert_eq!(
+            play(fen, "b1b8").unwrap(),
+            "1R4kr/8/8/8/8/8/8/6KR b Hh - 0 1"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::Display;
+
+use chb_chess::Move;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    clock::ClockState,
+    game::{GameAction, GameResult, History, Offers},
+    join::SeatGrant,
+    position::{MoveUpdate, Snapshot},
+};
+
+/// Bumped whenever a change to the messages would break older clients
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// Messages sent from the browser to the server over a WebSocket
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Must be the first message sent on a socket
+    Hello { version: u32 },
+    Move(Move),
+    /// Asks for a [`ServerMessage::Snapshot`] after noticing the local board is wrong
+    Resync,
+    /// Only accepted from players
+    Action(GameAction),
+}
+
+/// Messages sent from the server to the browser over a WebSocket
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Always the first message sent on a socket
+    Hello { version: u32 },
+    /// Sent to a player once they have a seat
+    Seat(SeatGrant),
+    /// The full position, sent when (re)syncing and after a takeback
+    Snapshot(Snapshot),
+    /// The whole game so far, sent to spectators when (re)syncing
+    History(History),
+    Move(MoveUpdate),
+    Clock(ClockState),
+    Result(GameResult),
+    /// Sent whenever an offer is made or answered
+    Offers(Offers),
+    MoveRejected(MoveRejection),
+    Error(String),
+    /// The server is shutting down. The game is saved and can be rejoined once it's back.
+    Restarting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    IllegalMove,
+    NotYourTurn,
+    ParseError,
+}
+
+/// Sent to a player when something they sent was not accepted as a move
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MoveRejection {
+    /// What the player sent, as text
+    pub text: String,
+    pub reason: RejectReason,
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            RejectReason::IllegalMove => "illegal move",
+            RejectReason::NotYourTurn => "not your turn",
+            RejectReason::ParseError => "parse error",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for MoveRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.reason, self.text)
+    }
+}
+
+/// Encoding used to put messages on the wire
+pub trait WireMessage: Serialize + DeserializeOwned {
+    fn encode(&self) -> String {
+        serde_json::to_string(self).expect("Messages are always serializable")
+    }
+
+    fn decode(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+}
+
+impl WireMessage for ClientMessage {}
+impl WireMessage for ServerMessage {}
@@ -0,0 +1,112 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Bonus, TimeControl};
+
+/// Speed of a game. Players have a separate rating for each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    /// Untimed games
+    Correspondence,
+}
+
+impl Category {
+    /// Sorts by the expected length of a 40 move game
+    pub fn of(time_control: Option<TimeControl>) -> Self {
+        let Some(tc) = time_control else {
+            return Self::Correspondence;
+        };
+        let per_move = match tc.bonus {
+            Bonus::None => 0,
+            Bonus::Increment(ms) | Bonus::Bronstein(ms) | Bonus::Delay(ms) => ms,
+        };
+        match (tc.base + 40 * per_move) / 1000 {
+            0..=179 => Self::Bullet,
+            180..=479 => Self::Blitz,
+            480..=1499 => Self::Rapid,
+            _ => Self::Classical,
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Bullet => "bullet",
+            Self::Blitz => "blitz",
+            Self::Rapid => "rapid",
+            Self::Classical => "classical",
+            Self::Correspondence => "correspondence",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bullet" => Ok(Self::Bullet),
+            "blitz" => Ok(Self::Blitz),
+            "rapid" => Ok(Self::Rapid),
+            "classical" => Ok(Self::Classical),
+            "correspondence" => Ok(Self::Correspondence),
+            _ => Err(format!("Unknown category {s}")),
+        }
+    }
+}
+
+/// A player's Glicko-2 rating in one category
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub category: Category,
+    pub rating: f64,
+    pub deviation: f64,
+    pub games: u32,
+    /// The rating is still too uncertain to mean much
+    pub provisional: bool,
+}
+
+impl Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.0}", self.rating)?;
+        if self.provisional {
+            write!(f, "?")?;
+        }
+        Ok(())
+    }
+}
+
+/// A rating just after a rated game
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub category: Category,
+    pub game_id: String,
+    pub rating: f64,
+    pub deviation: f64,
+    /// Unix timestamp in seconds
+    pub recorded_at: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub name: String,
+    pub ratings: Vec<Rating>,
+    /// Oldest first
+    pub history: Vec<RatingChange>,
+}
+
+/// Someone sitting at a board
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerInfo {
+    pub name: String,
+    /// Only registered users are rated, in the game's category
+    pub rating: Option<Rating>,
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::TimeControl, join::SeatGrant, protocol::WireMessage, rating::PlayerInfo};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorPreference {
+    #[default]
+    Random,
+    White,
+    Black,
+}
+
+/// The game someone is looking for
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CreateSeek {
+    /// Untimed if `None`
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    /// The color the seeker wants to play
+    #[serde(default)]
+    pub color: ColorPreference,
+    /// Only registered users can post or accept rated seeks
+    #[serde(default)]
+    pub rated: bool,
+    /// Lowest and highest opponent rating accepted, inclusive. Unrated players can't accept
+    /// seeks with a range.
+    #[serde(default)]
+    pub rating_range: Option<(u32, u32)>,
+}
+
+/// An open seek in the lobby
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Seek {
+    pub id: u64,
+    pub player: PlayerInfo,
+    pub options: CreateSeek,
+}
+
+/// Messages sent from the browser over the lobby socket, after the usual hello
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum LobbyRequest {
+    Seek(CreateSeek),
+    Cancel(u64),
+    Accept(u64),
+}
+
+/// Messages sent from the server over the lobby socket
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum LobbyMessage {
+    /// Every open seek, sent when first connecting
+    Seeks(Vec<Seek>),
+    Added(Seek),
+    Removed(u64),
+    /// The id given to a seek this socket posted
+    Posted(u64),
+    /// A game was made from a seek this socket posted or accepted. Join it with the grant's
+    /// token.
+    Matched(SeatGrant),
+    Error(String),
+}
+
+impl WireMessage for LobbyRequest {}
+impl WireMessage for LobbyMessage {}
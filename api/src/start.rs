@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of Chess960 starting positions
+pub const CHESS960_POSITIONS: u16 = 960;
+/// The Chess960 number of the standard starting position
+pub const STANDARD_CHESS960: u16 = 518;
+
+/// Where a new game starts from, for when a `BoardBuilder` is more than needed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPosition {
+    #[default]
+    Standard,
+    /// Chess960 start by its number, 0–959. A random one is picked if `None`.
+    Chess960(Option<u16>),
+    Fen(String),
+    Handicap(Handicap),
+}
+
+/// Piece White gives up at the start of the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Handicap {
+    /// The queen's knight
+    Knight,
+    /// The queen's rook, along with castling on that side
+    Rook,
+    Queen,
+}
+
+impl StartPosition {
+    /// FEN of the start, with `random` choosing the Chess960 number if it isn't given. `None` if
+    /// the Chess960 number is out of range. Given FENs are returned as they are.
+    pub fn fen(&self, random: impl FnOnce() -> u16) -> Option<String> {
+        match self {
+            StartPosition::Standard => chess960_fen(STANDARD_CHESS960),
+            StartPosition::Chess960(number) => chess960_fen(number.unwrap_or_else(random)),
+            StartPosition::Fen(fen) => Some(fen.trim().to_owned()),
+            StartPosition::Handicap(handicap) => Some(handicap.fen().to_owned()),
+        }
+    }
+
+    /// Whether the start is fair enough for rated games
+    pub fn is_balanced(&self) -> bool {
+        matches!(self, StartPosition::Standard | StartPosition::Chess960(_))
+    }
+}
+
+impl Handicap {
+    pub fn fen(&self) -> &'static str {
+        match self {
+            Handicap::Knight => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1",
+            Handicap::Rook => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w Kkq - 0 1",
+            Handicap::Queen => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1",
+        }
+    }
+}
+
+/// FEN for Chess960 start `number`, using the standard (Scharnagl) numbering. Both sides can
+/// castle with either rook, which [`Position`](crate::position::Position) reads the Chess960 way
+/// when they aren't in the corners.
+pub fn chess960_fen(number: u16) -> Option<String> {
+    if number >= CHESS960_POSITIONS {
+        return None;
+    }
+    let mut rank = [None; 8];
+    let mut n = number as usize;
+    // Light squared bishop on b, d, f or h, then the dark squared one on a, c, e or g
+    rank[n % 4 * 2 + 1] = Some('B');
+    n /= 4;
+    rank[n % 4 * 2] = Some('B');
+    n /= 4;
+    place(&mut rank, n % 6, 'Q');
+    n /= 6;
+    // Knights on two of the five squares left, indexed before either is placed
+    let (first, second) = [
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (0, 4),
+        (1, 2),
+        (1, 3),
+        (1, 4),
+        (2, 3),
+        (2, 4),
+        (3, 4),
+    ][n];
+    place(&mut rank, second, 'N');
+    place(&mut rank, first, 'N');
+    // The king goes between the rooks
+    for piece in ['R', 'K', 'R'] {
+        place(&mut rank, 0, piece);
+    }
+
+    let white: String = rank.iter().flatten().collect();
+    let black = white.to_ascii_lowercase();
+    Some(format!(
+        "{black}/pppppppp/8/8/8/8/PPPPPPPP/{white} w KQkq - 0 1"
+    ))
+}
+
+/// Puts `piece` on the `index`th empty square of `rank`
+fn place(rank: &mut [Option<char>; 8], index: usize, piece: char) {
+    if let Some(square) = rank.iter_mut().filter(|s| s.is_none()).nth(index) {
+        *square = Some(piece);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::position::Position;
+
+    fn back_rank(number: u16) -> String {
+        let fen = chess960_fen(number).unwrap();
+        fen.split(['/', ' ']).nth(7).unwrap().to_owned()
+    }
+
+    #[test]
+    fn scharnagl_numbering() {
+        assert_eq!(back_rank(STANDARD_CHESS960), "RNBQKBNR");
+        assert_eq!(back_rank(0), "BBQNNRKR");
+        assert_eq!(back_rank(959), "RKRNNQBB");
+    }
+
+    #[test]
+    fn every_start_is_different_and_valid() {
+        let mut seen = HashSet::new();
+        for number in 0..CHESS960_POSITIONS {
+            let rank = back_rank(number);
+            assert!(seen.insert(rank.clone()), "{number} repeats {rank}");
+            let king = rank.find('K').unwrap();
+            assert!(rank.find('R').unwrap() < king && king < rank.rfind('R').unwrap());
+            let bishops: Vec<_> = rank.match_indices('B').map(|(i, _)| i % 2).collect();
+            assert_ne!(bishops[0], bishops[1], "{number} has same coloured bishops");
+        }
+    }
+
+    #[test]
+    fn castling_rights_follow_the_rooks() {
+        let castling = |number| {
+            let position = chess960_fen(number).unwrap().parse::<Position>().unwrap();
+            let fen = position.to_fen();
+            (
+                position.is_chess960(),
+                fen.split(' ').nth(2).unwrap().to_owned(),
+            )
+        };
+        assert_eq!(castling(STANDARD_CHESS960), (false, "KQkq".to_owned()));
+        // RBNQKNBR keeps the king and rooks where the board castles from
+        assert_eq!(castling(524), (false, "KQkq".to_owned()));
+        assert_eq!(castling(0), (true, "HFhf".to_owned()));
+        assert_eq!(castling(959), (true, "CAca".to_owned()));
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(chess960_fen(CHESS960_POSITIONS), None);
+    }
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+/// Body for registering or logging in
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Who the current session belongs to
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub name: String,
+    /// False for guests
+    pub registered: bool,
+}
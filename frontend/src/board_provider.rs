@@ -1,60 +1,311 @@
-use chb_chess::{Board, Color, Move};
-use futures::{stream::SplitStream, StreamExt};
+use std::cell::Cell;
+
+use api::{
+    game::{GameAction, History, Offers},
+    join::SeatGrant,
+    position::{position_hash, MoveUpdate, Position},
+    protocol::{ClientMessage, ServerMessage, WireMessage, PROTOCOL_VERSION},
+};
+use chb_chess::{Color, Move};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future,
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
 use gloo_net::websocket::{futures::WebSocket, Message};
 use leptos::*;
 
-// How to stop these from running when hydrating?
-pub type Provider = (ReadSignal<Board>, SignalSetter<Move>);
+use crate::socket::socket_url;
+
+/// State of the connection to a board
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connection {
+    Connecting,
+    /// The server has sent the board
+    Connected,
+    /// Closed before the board was sent, e.g. because the seat was taken
+    Refused,
+    /// Closed by the server shutting down, which keeps the game for when it's back
+    Restarting,
+    Closed,
+}
 
-pub fn spectate_board(cx: Scope, id: String) -> ReadSignal<Board> {
-    let ws = WebSocket::open(&format!("ws://localhost:3000/api/board/{id}/subscribe")).unwrap();
-    board_signal_from_stream(cx, ws.split().1)
+// Sockets are opened once running in the browser and closed once `cx` is disposed
+pub struct Provider {
+    pub board: ReadSignal<Position>,
+    pub connection: ReadSignal<Connection>,
+    pub last_move: ReadSignal<Option<Move>>,
+    pub make_move: SignalSetter<Move>,
+    pub offers: ReadSignal<Offers>,
+    pub act: SignalSetter<GameAction>,
 }
 
+/// Follows the board without taking a seat. Moves can't be made, so `make_move` does nothing.
+pub fn spectate_board(cx: Scope, id: String) -> Provider {
+    let (board, set_board) = create_signal(cx, Position::default());
+    let (connection, set_connection) = create_signal(cx, Connection::Connecting);
+    let (last_move, set_last_move) = create_signal(cx, None);
+    let (offers, set_offers) = create_signal(cx, Offers::default());
+    let setters = Setters {
+        board: set_board,
+        connection: set_connection,
+        last_move: set_last_move,
+        offers: set_offers,
+    };
+    connect(
+        cx,
+        move || socket_url(&format!("/api/board/{id}/subscribe")),
+        setters,
+    );
+
+    Provider {
+        board,
+        connection,
+        last_move,
+        make_move: SignalSetter::map(cx, |_: Move| ()),
+        offers,
+        act: SignalSetter::map(cx, |_: GameAction| ()),
+    }
+}
+
+/// Joins the board as `play_as`. Moves passed to the setter are shown straight away and sent
+/// to the server, which has the final say: rejected moves are rolled back.
 pub fn play_board(cx: Scope, id: String, play_as: Color) -> Provider {
     log!("Playing board {id} as {play_as}");
-    let (board, set_board) = create_signal(cx, Board::default());
-    spawn_local(async move {
-        let url = format!("ws://localhost:3000/api/board/join/{id}/{play_as}");
-        log!("Url: {url}");
-        let ws = WebSocket::open(&url).unwrap();
-        let (mut _write, read) = ws.split();
-        let board = board_signal_from_stream(cx, read);
-    });
+    let (board, set_board) = create_signal(cx, Position::default());
+    let (connection, set_connection) = create_signal(cx, Connection::Connecting);
+    let (last_move, set_last_move) = create_signal(cx, None);
+    let (offers, set_offers) = create_signal(cx, Offers::default());
+    let setters = Setters {
+        board: set_board,
+        connection: set_connection,
+        last_move: set_last_move,
+        offers: set_offers,
+    };
+    let url = move || {
+        let mut url = socket_url(&format!("/api/board/join/{id}/{play_as}"))?;
+        if let Some(token) = stored_token(&id, play_as) {
+            url.push_str(&format!("?token={token}"));
+        }
+        Some(url)
+    };
+    let sender = connect(cx, url, setters);
 
-    let make_move = SignalSetter::map(cx, |_mv: Move| {
-        todo!();
+    let move_sender = sender.clone();
+    let make_move = SignalSetter::map(cx, move |mv: Move| {
+        let mut legal = false;
+        set_board.update(|b| legal = b.make(mv).is_ok());
+        if legal {
+            set_last_move(Some(mv));
+            _ = move_sender.unbounded_send(ClientMessage::Move(mv));
+        } else {
+            log!("Illegal move {mv}");
+        }
+    });
+    let act = SignalSetter::map(cx, move |action: GameAction| {
+        _ = sender.unbounded_send(ClientMessage::Action(action));
     });
 
-    (board, make_move)
+    Provider {
+        board,
+        connection,
+        last_move,
+        make_move,
+        offers,
+        act,
+    }
 }
 
-fn board_signal_from_stream(cx: Scope, stream: SplitStream<WebSocket>) -> ReadSignal<Board> {
-    let (board, set_board) = create_signal(cx, Board::default());
+/// Opens a socket to `url` once running in the browser and keeps `setters` following the
+/// server. Messages can be queued on the returned channel straight away, after the hello, and
+/// are sent once connected. The socket is closed once `cx` is disposed.
+fn connect(
+    cx: Scope,
+    url: impl FnOnce() -> Option<String> + 'static,
+    setters: Setters,
+) -> UnboundedSender<ClientMessage> {
+    let (tx, rx) = mpsc::unbounded();
+    _ = tx.unbounded_send(ClientMessage::Hello {
+        version: PROTOCOL_VERSION,
+    });
+    let closer = tx.clone();
+    on_cleanup(cx, move || closer.close_channel());
+    // Effects only run in the browser, and this one only once
+    let pending = Cell::new(Some((url, rx, tx.clone())));
+    create_effect(cx, move |_| {
+        let Some((url, rx, sender)) = pending.take() else {
+            return;
+        };
+        let Some(url) = url() else {
+            log!("Failed to connect to board: the page has no location");
+            (setters.connection)(Connection::Closed);
+            return;
+        };
+        let ws = match WebSocket::open(&url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                log!("Failed to connect to board: {e}");
+                (setters.connection)(Connection::Closed);
+                return;
+            }
+        };
+        let (write, read) = ws.split();
+        spawn_writer(write, rx);
+        follow_server(cx, read, sender, setters);
+    });
+    tx
+}
+
+/// Sends everything queued on `rx` over `write`, closing the socket once the channel is closed
+fn spawn_writer(
+    mut write: SplitSink<WebSocket, Message>,
+    mut rx: UnboundedReceiver<ClientMessage>,
+) {
     spawn_local(async move {
-        stream
-            .for_each(|m| async move {
-                let Ok(Message::Text(m)) = m else {
-                    return;
-                };
-                match m.split_once(':') {
-                    Some(("fen", f)) => {
-                        if let Ok(b) = f.trim().parse::<Board>() {
-                            set_board(b);
-                        }
+        while let Some(msg) = rx.next().await {
+            if let Err(e) = write.send(Message::Text(msg.encode())).await {
+                log!("Failed to send message: {e}");
+                break;
+            }
+        }
+        _ = write.close().await;
+    });
+}
+
+/// Signals kept up to date with the server
+#[derive(Clone, Copy)]
+struct Setters {
+    board: WriteSignal<Position>,
+    connection: WriteSignal<Connection>,
+    last_move: WriteSignal<Option<Move>>,
+    offers: WriteSignal<Offers>,
+}
+
+/// Keeps the board showing the server's board, overwriting any moves made locally. Stops once
+/// `cx` is disposed.
+fn follow_server(
+    cx: Scope,
+    mut stream: SplitStream<WebSocket>,
+    sender: UnboundedSender<ClientMessage>,
+    setters: Setters,
+) {
+    let (task, handle) = future::abortable(async move {
+        let mut connected = false;
+        let mut restarting = false;
+        // The board as the server last described it, its number of moves and the last of them
+        let mut server_board = Position::default();
+        let mut ply = 0;
+        let mut last_move = None;
+        // Set while waiting for a snapshot, so moves aren't applied to a known bad board
+        let mut resyncing = false;
+        while let Some(m) = stream.next().await {
+            let Ok(Message::Text(m)) = m else {
+                continue;
+            };
+            // Anything that could change the board also undoes unconfirmed local moves
+            let mut refresh = true;
+            match ServerMessage::decode(&m) {
+                Ok(ServerMessage::Hello { version }) if version != PROTOCOL_VERSION => {
+                    log!("Server speaks protocol version {version}, expected {PROTOCOL_VERSION}");
+                }
+                Ok(ServerMessage::Snapshot(s)) => match s.fen.trim().parse::<Position>() {
+                    Ok(b) => {
+                        server_board = b;
+                        ply = s.ply;
+                        // Snapshots don't say how the position was reached
+                        last_move = None;
+                        resyncing = false;
+                        connected = true;
+                    }
+                    Err(_) => log!("Received an invalid snapshot"),
+                },
+                Ok(ServerMessage::History(h)) => match replay(&h) {
+                    Some(b) => {
+                        server_board = b;
+                        ply = h.moves.len();
+                        last_move = h.moves.last().map(|entry| entry.mv);
+                        resyncing = false;
+                        connected = true;
                     }
-                    Some(("move", m)) if m.trim().parse::<Move>().is_ok() => {
-                        set_board.update(|b| {
-                            if b.make(m.trim().parse().expect("Validated")).is_err() {
-                                log!("BOARD OUT OF SYNC!");
-                            }
-                        });
+                    None => log!("Received an invalid game history"),
+                },
+                Ok(ServerMessage::Move(update)) if update.ply <= ply || resyncing => (),
+                Ok(ServerMessage::Move(update)) => {
+                    if apply(&mut server_board, ply, update) {
+                        ply = update.ply;
+                        last_move = Some(update.mv);
+                    } else {
+                        log!("Board out of sync, resyncing");
+                        resyncing = true;
+                        _ = sender.unbounded_send(ClientMessage::Resync);
                     }
-                    Some(_) => (),
-                    None => (),
                 }
-            })
-            .await;
+                Ok(ServerMessage::Seat(grant)) => {
+                    store_token(&grant);
+                    refresh = false;
+                }
+                Ok(ServerMessage::Offers(o)) => {
+                    (setters.offers)(o);
+                    refresh = false;
+                }
+                Ok(ServerMessage::Restarting) => {
+                    restarting = true;
+                    refresh = false;
+                }
+                Ok(ServerMessage::MoveRejected(r)) => log!("Move rejected: {r}"),
+                Ok(ServerMessage::Error(e)) => log!("Server error: {e}"),
+                Ok(_) => refresh = false,
+                Err(e) => log!("Failed to decode message from server: {e}"),
+            }
+            if connected && refresh && !resyncing {
+                (setters.connection)(Connection::Connected);
+                (setters.board)(server_board.clone());
+                (setters.last_move)(last_move);
+            }
+        }
+        (setters.connection)(if restarting {
+            Connection::Restarting
+        } else if connected {
+            Connection::Closed
+        } else {
+            Connection::Refused
+        });
     });
-    board
+    on_cleanup(cx, move || handle.abort());
+    spawn_local(async move {
+        _ = task.await;
+    });
+}
+
+/// Makes the server's move on `board`, which is at `ply`. Returns false if the result doesn't
+/// match the server's position.
+fn apply(board: &mut Position, ply: usize, update: MoveUpdate) -> bool {
+    update.ply == ply + 1 && board.make(update.mv).is_ok() && position_hash(board) == update.hash
+}
+
+/// The position reached at the end of `history`
+fn replay(history: &History) -> Option<Position> {
+    let mut board = history.start_fen.parse::<Position>().ok()?;
+    for entry in &history.moves {
+        board.make(entry.mv).ok()?;
+    }
+    Some(board)
+}
+
+fn seat_key(id: &str, color: Color) -> String {
+    format!("seat-{id}-{color}")
+}
+
+/// Token for getting back into a seat taken earlier from this browser
+fn stored_token(id: &str, color: Color) -> Option<String> {
+    let storage = web_sys::window()?.local_storage().ok()??;
+    storage.get_item(&seat_key(id, color)).ok()?
+}
+
+pub fn store_token(grant: &SeatGrant) {
+    let storage = web_sys::window().and_then(|w| w.local_storage().ok().flatten());
+    if let Some(storage) = storage {
+        _ = storage.set_item(&seat_key(&grant.id, grant.color), &grant.token);
+    }
 }
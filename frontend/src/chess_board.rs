@@ -1,4 +1,5 @@
-use chb_chess::{Board, Color, Move, Square};
+use api::position::Position;
+use chb_chess::{Color, Move, Piece, PieceKind, Square};
 use leptos::*;
 
 use piece::{PieceDisplay, PieceDisplayProps};
@@ -8,12 +9,109 @@ mod piece;
 #[component]
 pub fn ChessBoard(
     cx: Scope,
-    #[prop(into)] board: Signal<Board>,
+    #[prop(into)] board: Signal<Position>,
     #[prop(into)] make_move: SignalSetter<Move>,
     #[prop(into)] view_as: MaybeSignal<Color>,
     #[prop(into)] play_as: MaybeSignal<Option<Color>>,
+    /// Promote pawns to queens without showing the chooser
+    #[prop(optional, into)]
+    auto_queen: MaybeSignal<bool>,
+    /// Highlighted along with the board
+    #[prop(optional, into)]
+    last_move: MaybeSignal<Option<Move>>,
 ) -> impl IntoView {
     let reverse_board = Signal::derive(cx, move || view_as() == Color::Black);
+    let legal_moves = create_memo(cx, move |_| board.with(Position::moves));
+    // Piece picked up by clicking, waiting for a click on where it should go
+    let (selected, set_selected) = create_signal(cx, None::<Square>);
+    let targets = create_memo(cx, move |_| {
+        let Some(origin) = selected() else {
+            return Vec::new();
+        };
+        legal_moves.with(|moves| {
+            moves
+                .iter()
+                .filter(|mv| mv.origin == origin)
+                .map(|mv| mv.dest)
+                .collect::<Vec<_>>()
+        })
+    });
+    // The king of the side to move, if it is in check
+    let checked_king = create_memo(cx, move |_| {
+        board.with(|b| {
+            if !b.in_check() {
+                return None;
+            }
+            let king = Piece::Filled(PieceKind::King, b.color_to_move());
+            (0u32..64)
+                .filter_map(|i| Square::try_from(i).ok())
+                .find(|sqr| b[*sqr] == king)
+        })
+    });
+
+    // A pawn move to the last rank, waiting on a piece to promote to
+    let (promoting, set_promoting) = create_signal(cx, None::<(Move, Color)>);
+    let try_move = move |mv: Move| {
+        set_selected(None);
+        let legal = legal_moves.with(|moves| {
+            moves
+                .iter()
+                .any(|m| m.origin == mv.origin && m.dest == mv.dest)
+        });
+        if !legal {
+            log!("Illegal move {mv}");
+            return;
+        }
+        let Piece::Filled(PieceKind::Pawn, color) = board.with(|b| b[mv.origin]) else {
+            make_move.set(mv);
+            return;
+        };
+        let last_rank = match color {
+            Color::White => 7,
+            Color::Black => 0,
+        };
+        if mv.dest.rank() != last_rank {
+            make_move.set(mv);
+        } else if auto_queen() {
+            make_move.set(Move {
+                promotion: Piece::Filled(PieceKind::Queen, color),
+                ..mv
+            });
+        } else {
+            set_promoting(Some((mv, color)));
+        }
+    };
+    let chooser = move || {
+        let (mv, color) = promoting()?;
+        let options = [
+            PieceKind::Queen,
+            PieceKind::Rook,
+            PieceKind::Bishop,
+            PieceKind::Knight,
+        ]
+        .into_iter()
+        .map(|kind| {
+            let choose = move |_| {
+                set_promoting(None);
+                make_move.set(Move {
+                    promotion: Piece::Filled(kind, color),
+                    ..mv
+                });
+            };
+            view! {
+                cx,
+                <div class=format!("piece kind-{color}{kind}") on:click=choose/>
+            }
+        })
+        .collect::<Vec<_>>();
+        Some(view! {
+            cx,
+            <div class="promotion-chooser">
+                {options}
+                <button on:click=move |_| set_promoting(None)>"Cancel"</button>
+            </div>
+        })
+    };
     let squares = move || {
         log!("Running memo");
         let mut list = (0u32..64u32)
@@ -35,26 +133,58 @@ pub fn ChessBoard(
 
     view! {
         cx,
-        <div class="chess-board">
-            <For
-                each=squares
-                key=|sqr| sqr.0.to_string()
-                view=move |cx, (sqr, piece, may_move)| {
-                    let even = (sqr.rank() + sqr.file()) % 2 == 0;
-                    view! {
-                        cx,
-                        <div class="square" class:dark=even>
-                            <PieceDisplay
-                                piece=piece
-                                square=sqr
-                                board_reversed=reverse_board
-                                may_move=may_move
-                                make_move=make_move
-                            />
-                        </div>
+        <>
+            <div class="chess-board">
+                <For
+                    each=squares
+                    key=|sqr| sqr.0.to_string()
+                    view=move |cx, (sqr, piece, may_move)| {
+                        let even = (sqr.rank() + sqr.file()) % 2 == 0;
+                        let is_target = move || targets.with(|t| t.contains(&sqr));
+                        let class = move || {
+                            let in_last_move = last_move()
+                                .is_some_and(|mv| mv.origin == sqr || mv.dest == sqr);
+                            let highlights = [
+                                (even, "dark"),
+                                (selected() == Some(sqr), "selected"),
+                                (is_target(), "target"),
+                                (in_last_move, "last-move"),
+                                (checked_king() == Some(sqr), "check"),
+                            ];
+                            highlights
+                                .into_iter()
+                                .filter(|(on, _)| *on)
+                                .fold("square".to_owned(), |class, (_, name)| class + " " + name)
+                        };
+                        let click = move |_| {
+                            if let Some(origin) = selected().filter(|_| is_target()) {
+                                try_move(Move {
+                                    origin,
+                                    dest: sqr,
+                                    promotion: Piece::Empty,
+                                });
+                            } else if may_move() && selected() != Some(sqr) {
+                                set_selected(Some(sqr));
+                            } else {
+                                set_selected(None);
+                            }
+                        };
+                        view! {
+                            cx,
+                            <div class=class on:click=click>
+                                <PieceDisplay
+                                    piece=piece
+                                    square=sqr
+                                    board_reversed=reverse_board
+                                    may_move=may_move
+                                    make_move=try_move
+                                />
+                            </div>
+                        }
                     }
-                }
-            />
-        </div>
+                />
+            </div>
+            {chooser}
+        </>
     }
 }
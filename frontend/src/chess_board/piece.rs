@@ -1,6 +1,6 @@
 use chb_chess::{Dir, Move, Piece, Square};
 use leptos::*;
-use web_sys::{HtmlDivElement, MouseEvent};
+use web_sys::{HtmlDivElement, PointerEvent};
 
 #[component]
 pub fn PieceDisplay<F>(
@@ -14,13 +14,14 @@ pub fn PieceDisplay<F>(
 where
     F: Fn(Move) + 'static,
 {
-    let (held, set_held) = create_signal(cx, false);
-    let mouse = use_context::<ReadSignal<(i32, i32)>>(cx).expect("Mouse signal to exist");
+    // The pointer dragging the piece, if any. Other pointers (e.g. a second finger) are ignored.
+    let (held, set_held) = create_signal(cx, None::<i32>);
+    let (pointer, set_pointer) = create_signal(cx, (0, 0));
     let (origin, set_origin) = create_signal(cx, (0, 0));
     let translate = (move || {
-        let (m_x, m_y) = mouse();
+        let (p_x, p_y) = pointer();
         let (o_x, o_y) = origin();
-        (m_x - o_x, m_y - o_y)
+        (p_x - o_x, p_y - o_y)
     })
     .derive_signal(cx);
     let class = move || match piece() {
@@ -28,17 +29,19 @@ where
         Piece::Empty => "empty".to_owned(),
     };
     let transformation = move || {
-        if held() {
+        if held().is_some() {
             translate.with(|(x, y)| format!("transform: translate({x}px, {y}px)"))
         } else {
             "".to_string()
         }
     };
+    let is_held = move |e: &PointerEvent| held() == Some(e.pointer_id());
 
-    let mouse_up = move |e: MouseEvent| {
-        if !held() {
+    let pointer_up = move |e: PointerEvent| {
+        if !is_held(&e) {
             return;
         }
+        set_held(None);
         let target: HtmlDivElement = event_target(&e);
         let (x, y) = translate();
         let dx = (x.abs() + target.offset_width() / 2) / target.offset_width();
@@ -60,6 +63,9 @@ where
         }
         log!("Moving to {:?}", dest);
         match dest {
+            // Dropped where it started, which the square takes as a click
+            Some(d) if d == square => (),
+            // The board asks which piece to promote to, if any
             Some(d) => make_move(Move {
                 origin: square,
                 dest: d,
@@ -67,17 +73,35 @@ where
             }),
             _ => log!("Invalid move targets"),
         }
-        set_held(false);
     };
 
-    let mouse_down = move |e: MouseEvent| {
-        if may_move() {
-            let target: HtmlDivElement = event_target(&e);
-            set_held(true);
-            set_origin((
-                target.offset_left() + target.offset_width() / 2,
-                target.offset_top() + target.offset_height() / 2,
-            ));
+    let pointer_down = move |e: PointerEvent| {
+        // Only the main button of a mouse or pen, touches always count
+        if !may_move() || held().is_some() || e.button() != 0 {
+            return;
+        }
+        let target: HtmlDivElement = event_target(&e);
+        // Keeps the piece getting the pointer's events even once it leaves the piece
+        _ = target.set_pointer_capture(e.pointer_id());
+        let rect = target.get_bounding_client_rect();
+        set_origin((
+            (rect.left() + rect.width() / 2.0) as i32,
+            (rect.top() + rect.height() / 2.0) as i32,
+        ));
+        set_pointer((e.client_x(), e.client_y()));
+        set_held(Some(e.pointer_id()));
+    };
+
+    let pointer_move = move |e: PointerEvent| {
+        if is_held(&e) {
+            set_pointer((e.client_x(), e.client_y()));
+        }
+    };
+
+    // The browser took the pointer, e.g. to scroll, so the piece goes back
+    let pointer_cancel = move |e: PointerEvent| {
+        if is_held(&e) {
+            set_held(None);
         }
     };
 
@@ -85,10 +109,14 @@ where
         cx,
         <div
             class=move || format!("piece kind-{}", class())
-            class:held=held
+            class:held=move || held().is_some()
+            class:movable=may_move
             prop:style=transformation
-            on:mousedown=mouse_down
-            on:mouseup=mouse_up
+            on:pointerdown=pointer_down
+            on:pointermove=pointer_move
+            on:pointerup=pointer_up
+            on:pointercancel=pointer_cancel
+            on:lostpointercapture=pointer_cancel
         />
     }
 }
@@ -1,21 +1,27 @@
 use cfg_if::cfg_if;
-use leptos::{component, create_signal, provide_context, view, IntoView, Scope};
+use leptos::{component, view, IntoView, Scope};
 use leptos_meta::*;
 use leptos_router::*;
 use routes::home::*;
 use routes::play::*;
-use web_sys::MouseEvent;
+use routes::watch::*;
 
 mod board_provider;
 mod chess_board;
+mod lobby_provider;
+mod preferences;
 mod routes;
+mod socket;
 
 #[component]
-pub fn App(cx: Scope) -> impl IntoView {
+pub fn App(
+    cx: Scope,
+    /// Where the browser should open WebSockets, like "wss://example.com/chess". Defaults to
+    /// the page's own origin.
+    #[prop(optional_no_strip)]
+    ws_origin: Option<String>,
+) -> impl IntoView {
     provide_meta_context(cx);
-    let (mouse, set_mouse) = create_signal(cx, (0, 0));
-    provide_context(cx, mouse);
-    let mouse_move = move |e: MouseEvent| set_mouse((e.client_x(), e.client_y()));
     let formatter = |text| format!("{text} - Chess!");
 
     view! {
@@ -24,13 +30,15 @@ pub fn App(cx: Scope) -> impl IntoView {
             <Stylesheet href="/pkg/web_chess.css"/>
             <Meta name="description" content="Leptos chess website"/>
             <Title text="Chess!" formatter />
+            {ws_origin.map(|origin| view! {cx, <Meta name=socket::ORIGIN_META content=origin/>})}
 
             <Router>
-                <main on:mousemove=mouse_move>
+                <main>
                     <Routes>
                         <Route path="/" view=move |cx| view! {cx, <Home/>}/>
                         <Route path="play" view=move |cx| view! {cx, <Play/>}/>
                         <Route path="play/:id" view=move |cx| view! {cx,  <Play/>}/>
+                        <Route path="watch" view=move |cx| view! {cx, <Watch/>}/>
                     </Routes>
                 </main>
             </Router>
@@ -0,0 +1,106 @@
+use std::cell::Cell;
+
+use api::{
+    join::SeatGrant,
+    protocol::{ClientMessage, ServerMessage, WireMessage, PROTOCOL_VERSION},
+    seek::{LobbyMessage, LobbyRequest, Seek},
+};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    future,
+    SinkExt, StreamExt,
+};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use leptos::*;
+
+use crate::board_provider::store_token;
+use crate::socket::socket_url;
+
+pub struct LobbyProvider {
+    pub seeks: ReadSignal<Vec<Seek>>,
+    /// Ids of the seeks posted from this page
+    pub own: ReadSignal<Vec<u64>>,
+    /// Set once one of our seeks is accepted or we accept one
+    pub matched: ReadSignal<Option<SeatGrant>>,
+    pub requests: UnboundedSender<LobbyRequest>,
+}
+
+/// Connects to the lobby once the page is running in the browser. The socket is closed once `cx`
+/// is disposed.
+pub fn join_lobby(cx: Scope) -> LobbyProvider {
+    let (seeks, set_seeks) = create_signal(cx, Vec::<Seek>::new());
+    let (own, set_own) = create_signal(cx, Vec::new());
+    let (matched, set_matched) = create_signal(cx, None);
+    let (requests, queued) = mpsc::unbounded::<LobbyRequest>();
+    let closer = requests.clone();
+    on_cleanup(cx, move || closer.close_channel());
+    // Effects only run in the browser, and this one only once
+    let queued = Cell::new(Some(queued));
+
+    create_effect(cx, move |_| {
+        let Some(mut queued) = queued.take() else {
+            return;
+        };
+        let Some(url) = socket_url("/api/lobby") else {
+            log!("Failed to join lobby: the page has no location");
+            return;
+        };
+        let ws = match WebSocket::open(&url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                log!("Failed to join lobby: {e}");
+                return;
+            }
+        };
+        let (mut write, mut read) = ws.split();
+        spawn_local(async move {
+            let hello = ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            };
+            if write.send(Message::Text(hello.encode())).await.is_ok() {
+                while let Some(request) = queued.next().await {
+                    if let Err(e) = write.send(Message::Text(request.encode())).await {
+                        log!("Failed to send lobby request: {e}");
+                        break;
+                    }
+                }
+            }
+            _ = write.close().await;
+        });
+        let (task, handle) = future::abortable(async move {
+            while let Some(m) = read.next().await {
+                let Ok(Message::Text(m)) = m else {
+                    continue;
+                };
+                match LobbyMessage::decode(&m) {
+                    Ok(LobbyMessage::Seeks(s)) => set_seeks(s),
+                    Ok(LobbyMessage::Added(seek)) => set_seeks.update(|s| s.push(seek)),
+                    Ok(LobbyMessage::Removed(id)) => {
+                        set_seeks.update(|s| s.retain(|seek| seek.id != id));
+                        set_own.update(|o| o.retain(|own| *own != id));
+                    }
+                    Ok(LobbyMessage::Posted(id)) => set_own.update(|o| o.push(id)),
+                    Ok(LobbyMessage::Matched(grant)) => {
+                        store_token(&grant);
+                        set_matched(Some(grant));
+                    }
+                    Ok(LobbyMessage::Error(e)) => log!("Lobby error: {e}"),
+                    // The hello from the shared handshake
+                    Err(_) if ServerMessage::decode(&m).is_ok() => (),
+                    Err(e) => log!("Failed to decode lobby message: {e}"),
+                }
+            }
+        });
+        on_cleanup(cx, move || handle.abort());
+        spawn_local(async move {
+            _ = task.await;
+        });
+    });
+
+    LobbyProvider {
+        seeks,
+        own,
+        matched,
+        requests,
+    }
+}
@@ -0,0 +1,20 @@
+//! Settings kept in the browser's local storage
+
+const AUTO_QUEEN: &str = "auto-queen";
+
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Whether pawns promote to a queen without asking
+pub fn auto_queen() -> bool {
+    storage()
+        .and_then(|s| s.get_item(AUTO_QUEEN).ok().flatten())
+        .is_some_and(|v| v == "true")
+}
+
+pub fn set_auto_queen(enabled: bool) {
+    if let Some(storage) = storage() {
+        _ = storage.set_item(AUTO_QUEEN, &enabled.to_string());
+    }
+}
@@ -1,35 +1,334 @@
-use chb_chess::BoardBuilder;
+use std::rc::Rc;
+
+use api::{
+    clock::{Bonus, TimeControl},
+    create::CreateBoard,
+    position::Position,
+    seek::{ColorPreference, CreateSeek, LobbyRequest, Seek},
+    start::{Handicap, StartPosition, CHESS960_POSITIONS},
+};
 use gloo_net::http::Request;
 use leptos::*;
 use leptos_meta::{Title, TitleProps};
-use leptos_router::{AProps, A};
+use leptos_router::{use_navigate, AProps, A};
+
+use crate::lobby_provider::{join_lobby, LobbyProvider};
+
+/// Time controls that can be sought, as minutes and increment seconds
+const TIME_CONTROLS: [Option<(u64, u64)>; 7] = [
+    None,
+    Some((1, 0)),
+    Some((3, 2)),
+    Some((5, 0)),
+    Some((10, 5)),
+    Some((15, 10)),
+    Some((30, 0)),
+];
 
 #[component]
 pub fn Home(cx: Scope) -> impl IntoView {
-    //let (id, set_id) = create_signal(cx, "".to_string());
-    let id = create_local_resource(
-        cx,
-        || "",
-        move |_| async {
-            let req = Request::post("/api/board/create")
-                .json(&BoardBuilder::default())
-                .unwrap();
-            req.send().await.unwrap().text().await.unwrap()
-        },
-    );
     view! {
         cx,
         <>
             <Title text="Home"/>
             <div class="content">
-                <Suspense fallback=move || view! { cx, <p>"Loading..."</p>}>
-                    <A href=move || format!("/play/{}", id.read(cx).unwrap_or("".to_owned()))>
-                        <button >"Play"</button>
-                    </A>
-                    <br/>
-                    <h1>"Hello"</h1>
-                </Suspense>
+                <A href="/watch">
+                    <button>"Watch games"</button>
+                </A>
+                <br/>
+                <h1>"Hello"</h1>
+                <NewGame/>
+                <Lobby/>
             </div>
         </>
     }
 }
+
+/// Form for creating a game to share, from the standard position or another start
+#[component]
+fn NewGame(cx: Scope) -> impl IntoView {
+    let (kind, set_kind) = create_signal(cx, "standard".to_owned());
+    let (number, set_number) = create_signal(cx, String::new());
+    let (fen, set_fen) = create_signal(cx, String::new());
+    let (handicap, set_handicap) = create_signal(cx, Handicap::Knight);
+    let (time_control, set_time_control) = create_signal(cx, 0);
+    let (error, set_error) = create_signal(cx, None::<&'static str>);
+
+    let start = move || match kind().as_str() {
+        "chess960" => number.with(|n| match n.trim() {
+            "" => Ok(StartPosition::Chess960(None)),
+            n => n
+                .parse::<u16>()
+                .ok()
+                .filter(|n| *n < CHESS960_POSITIONS)
+                .map(|n| StartPosition::Chess960(Some(n)))
+                .ok_or("Chess960 starts are numbered 0 to 959"),
+        }),
+        "fen" => fen.with(|f| match f.trim().parse::<Position>() {
+            Ok(_) => Ok(StartPosition::Fen(f.trim().to_owned())),
+            Err(_) => Err("Not a valid FEN"),
+        }),
+        "handicap" => Ok(StartPosition::Handicap(handicap())),
+        _ => Ok(StartPosition::Standard),
+    };
+    let problem = move || start().err().or_else(error);
+
+    let navigate = Rc::new(use_navigate(cx));
+    let create = move |_| {
+        let Ok(chosen) = start() else {
+            return;
+        };
+        let request = CreateBoard {
+            start: Some(chosen),
+            time_control: preset(time_control()),
+            ..Default::default()
+        };
+        let navigate = navigate.clone();
+        spawn_local(async move {
+            match create_board(&request).await {
+                Some(id) => {
+                    _ = navigate(&format!("/play/{id}"), Default::default());
+                }
+                None => set_error(Some("Couldn't create the game")),
+            }
+        });
+    };
+    let time_control_options = (0..TIME_CONTROLS.len())
+        .map(|i| {
+            let label = preset(i).map_or("Untimed".to_owned(), |tc| tc.to_string());
+            view! { cx, <option value=i.to_string()>{label}</option> }
+        })
+        .collect::<Vec<_>>();
+    let options = move || match kind().as_str() {
+        "chess960" => Some(view! {
+            cx,
+            <input
+                type="number"
+                min="0"
+                max="959"
+                placeholder="Random"
+                on:input=move |e| set_number(event_target_value(&e))
+                prop:value=number
+            />
+        }),
+        "fen" => Some(view! {
+            cx,
+            <input
+                type="text"
+                class="fen"
+                placeholder="FEN"
+                on:input=move |e| set_fen(event_target_value(&e))
+                prop:value=fen
+            />
+        }),
+        "handicap" => Some(view! {
+            cx,
+            <select on:change=move |e| set_handicap(match event_target_value(&e).as_str() {
+                "rook" => Handicap::Rook,
+                "queen" => Handicap::Queen,
+                _ => Handicap::Knight,
+            })>
+                <option value="knight">"Knight odds"</option>
+                <option value="rook">"Rook odds"</option>
+                <option value="queen">"Queen odds"</option>
+            </select>
+        }),
+        _ => None,
+    };
+
+    view! {
+        cx,
+        <fieldset class="new-game">
+            <legend>"New game"</legend>
+            <select on:change=move |e| {
+                set_error(None);
+                set_kind(event_target_value(&e));
+            }>
+                <option value="standard">"Standard"</option>
+                <option value="chess960">"Chess960"</option>
+                <option value="fen">"From FEN"</option>
+                <option value="handicap">"Handicap"</option>
+            </select>
+            {options}
+            <select
+                on:change=move |e| set_time_control(event_target_value(&e).parse().unwrap_or(0))
+                prop:value=move || time_control().to_string()
+            >
+                {time_control_options}
+            </select>
+            <button on:click=create prop:disabled=move || start().is_err()>"Create"</button>
+            <p class="error">{problem}</p>
+        </fieldset>
+    }
+}
+
+async fn create_board(create: &CreateBoard) -> Option<String> {
+    let res = Request::post("/api/board/create")
+        .json(create)
+        .ok()?
+        .send()
+        .await
+        .ok()?;
+    if !res.ok() {
+        return None;
+    }
+    res.text().await.ok()
+}
+
+/// Open seeks, and a form for posting one. Goes to the game once a seek is accepted.
+#[component]
+fn Lobby(cx: Scope) -> impl IntoView {
+    let LobbyProvider {
+        seeks,
+        own,
+        matched,
+        requests,
+    } = join_lobby(cx);
+    let navigate = use_navigate(cx);
+    create_effect(cx, move |_| {
+        if let Some(grant) = matched() {
+            let path = format!("/play/{}?as={}", grant.id, grant.color);
+            _ = navigate(&path, Default::default());
+        }
+    });
+
+    let (time_control, set_time_control) = create_signal(cx, 3);
+    let (color, set_color) = create_signal(cx, ColorPreference::Random);
+    let (rated, set_rated) = create_signal(cx, false);
+    // Empty for no limit on that side
+    let (min_rating, set_min_rating) = create_signal(cx, None::<u32>);
+    let (max_rating, set_max_rating) = create_signal(cx, None::<u32>);
+    let post = {
+        let requests = requests.clone();
+        move |_| {
+            let seek = CreateSeek {
+                time_control: preset(time_control()),
+                color: color(),
+                rated: rated(),
+                rating_range: match (min_rating(), max_rating()) {
+                    (None, None) => None,
+                    (low, high) => Some((low.unwrap_or(0), high.unwrap_or(u32::MAX))),
+                },
+            };
+            _ = requests.unbounded_send(LobbyRequest::Seek(seek));
+        }
+    };
+    let time_control_options = (0..TIME_CONTROLS.len())
+        .map(|i| {
+            let label = preset(i).map_or("Untimed".to_owned(), |tc| tc.to_string());
+            view! { cx, <option value=i.to_string()>{label}</option> }
+        })
+        .collect::<Vec<_>>();
+
+    view! {
+        cx,
+        <div class="lobby">
+            <h2>"Lobby"</h2>
+            <fieldset>
+                <legend>"New seek"</legend>
+                <select
+                    on:change=move |e| set_time_control(event_target_value(&e).parse().unwrap_or(0))
+                    prop:value=move || time_control().to_string()
+                >
+                    {time_control_options}
+                </select>
+                <select on:change=move |e| set_color(match event_target_value(&e).as_str() {
+                    "white" => ColorPreference::White,
+                    "black" => ColorPreference::Black,
+                    _ => ColorPreference::Random,
+                })>
+                    <option value="random">"Random"</option>
+                    <option value="white">"White"</option>
+                    <option value="black">"Black"</option>
+                </select>
+                <label>
+                    <input
+                        type="checkbox"
+                        on:change=move |e| set_rated(event_target_checked(&e))
+                        prop:checked=move || rated()
+                    />
+                    "Rated"
+                </label>
+                <label>
+                    "Opponent rating from "
+                    <input
+                        type="number"
+                        min="0"
+                        on:change=move |e| set_min_rating(event_target_value(&e).parse().ok())
+                    />
+                    " to "
+                    <input
+                        type="number"
+                        min="0"
+                        on:change=move |e| set_max_rating(event_target_value(&e).parse().ok())
+                    />
+                </label>
+                <button on:click=post>"Seek"</button>
+            </fieldset>
+            <table class="seeks">
+                <For
+                    each=seeks
+                    key=|seek| seek.id
+                    view=move |cx, seek: Seek| {
+                        let id = seek.id;
+                        let requests = requests.clone();
+                        let is_own = move || own.with(|o| o.contains(&id));
+                        let act = move |_| {
+                            let request = if is_own() {
+                                LobbyRequest::Cancel(id)
+                            } else {
+                                LobbyRequest::Accept(id)
+                            };
+                            _ = requests.unbounded_send(request);
+                        };
+                        let player = match seek.player.rating {
+                            Some(rating) => format!("{} ({rating})", seek.player.name),
+                            None => seek.player.name,
+                        };
+                        let time_control = seek
+                            .options
+                            .time_control
+                            .map_or("Untimed".to_owned(), |tc| tc.to_string());
+                        let mode = if seek.options.rated { "Rated" } else { "Casual" };
+                        let range = match seek.options.rating_range {
+                            None => "Anyone".to_owned(),
+                            Some((low, u32::MAX)) => format!("{low}+"),
+                            Some((low, high)) => format!("{low}-{high}"),
+                        };
+                        let color = match seek.options.color {
+                            ColorPreference::Random => "Random",
+                            ColorPreference::White => "White",
+                            ColorPreference::Black => "Black",
+                        };
+                        view! {
+                            cx,
+                            <tr>
+                                <td>{player}</td>
+                                <td>{time_control}</td>
+                                <td>{mode}</td>
+                                <td>{color}</td>
+                                <td>{range}</td>
+                                <td>
+                                    <button on:click=act>
+                                        {move || if is_own() { "Cancel" } else { "Accept" }}
+                                    </button>
+                                </td>
+                            </tr>
+                        }
+                    }
+                />
+            </table>
+        </div>
+    }
+}
+
+fn preset(index: usize) -> Option<TimeControl> {
+    let (minutes, increment) = TIME_CONTROLS.get(index).copied().flatten()?;
+    Some(TimeControl {
+        base: minutes * 60_000,
+        bonus: match increment {
+            0 => Bonus::None,
+            s => Bonus::Increment(s * 1000),
+        },
+    })
+}
@@ -1,38 +1,109 @@
+use std::{cell::Cell, rc::Rc};
+
+use api::{
+    game::{GameAction, Offers},
+    position::Position,
+    rating::PlayerInfo,
+};
 use chb_chess::Color;
+use gloo_net::http::Request;
 use leptos::*;
-use leptos_router::use_params_map;
+use leptos_router::{use_navigate, use_params_map, use_query_map, NavigateOptions};
 use web_sys::Event;
 
-use crate::board_provider::play_board;
+use crate::board_provider::{play_board, spectate_board, Connection, Provider};
 use crate::chess_board::{ChessBoard, ChessBoardProps};
+use crate::preferences;
+
+/// Value of the `as` query parameter for watching without taking a seat
+pub const SPECTATE: &str = "spectate";
 
 #[component]
 pub fn Play(cx: Scope) -> impl IntoView {
     let params = use_params_map(cx);
 
-    let (board, make_move) = play_board(
-        cx,
-        params.with(|p| p.get("id").cloned().unwrap_or("1".to_owned())),
-        Color::White,
-    );
+    let id = params.with(|p| p.get("id").cloned().unwrap_or("1".to_owned()));
+    // The seat to take, or None to spectate. Set when coming from the lobby, which already
+    // picked a side, and by the role selector below.
+    let query = use_query_map(cx);
+    let role = create_memo(cx, move |_| {
+        query.with(|q| {
+            q.get("as")
+                .map_or(Some(Color::White), |c| c.parse::<Color>().ok())
+        })
+    });
+    let pgn_url = format!("/api/board/{id}/pgn");
+    let players_url = format!("/api/board/{id}/players");
+
+    // Refetched after every move, which also picks up rating changes once the game ends
+    let (position, set_position) = create_signal(cx, String::new());
+    let players = create_local_resource(cx, position, move |_| {
+        fetch_players(players_url.clone())
+    });
+    let player_name = move |color: Color| {
+        players
+            .read(cx)
+            .flatten()
+            .and_then(|players| players[color].clone())
+            .map(|p| match p.rating {
+                Some(rating) => format!("{} ({rating})", p.name),
+                None => p.name,
+            })
+            .unwrap_or("Waiting for player".to_owned())
+    };
+
+    let (auto_queen, set_auto_queen) = create_signal(cx, false);
+    // Local storage isn't there when rendering on the server
+    create_effect(cx, move |_| set_auto_queen(preferences::auto_queen()));
+    let toggle_auto_queen = move |e: Event| {
+        let enabled = event_target_checked(&e);
+        preferences::set_auto_queen(enabled);
+        set_auto_queen(enabled);
+    };
+
+    // Switching roles only changes the query, keeping the page
+    let navigate = Rc::new(use_navigate(cx));
+    let change_role = {
+        let id = id.clone();
+        move |e: Event| {
+            let path = format!("/play/{id}?as={}", event_target_value(&e));
+            let options = NavigateOptions {
+                replace: true,
+                ..Default::default()
+            };
+            _ = navigate(&path, options);
+        }
+    };
 
-    let (play_as, set_play_as) = create_signal(cx, Some(Color::White));
-    let view_as = Signal::derive(cx, move || play_as().unwrap_or(Color::White));
-    let change_player = move |e: Event| {
-        let player = event_target_value(&e).parse::<Color>().ok();
-        set_play_as(player);
+    // Scope holding the current connection, disposed to close it when the role changes
+    let connection = Rc::new(Cell::new(None::<ScopeDisposer>));
+    let board_view = move || {
+        let role = role();
+        let id = id.clone();
+        if let Some(old) = connection.take() {
+            old.dispose();
+        }
+        let (view, disposer) = cx.run_child_scope(|cx| match role {
+            Some(color) => view! {
+                cx,
+                <Seat id=id color=color auto_queen=auto_queen set_position=set_position/>
+            }
+            .into_view(cx),
+            None => view! { cx, <Spectator id=id set_position=set_position/> }.into_view(cx),
+        });
+        connection.set(Some(disposer));
+        view
     };
 
     view! {
         cx,
         <>
-            <ChessBoard
-                board=board
-                make_move=make_move
-                play_as=play_as
-                view_as=view_as
-            />
+            {board_view}
             <div class="board-controls">
+                <ul class="players">
+                    <li>"White: " {move || player_name(Color::White)}</li>
+                    <li>"Black: " {move || player_name(Color::Black)}</li>
+                </ul>
                 <fieldset>
                     <legend>"Play as"</legend>
                     <div>
@@ -41,8 +112,8 @@ pub fn Play(cx: Scope) -> impl IntoView {
                             id="play-as-white"
                             name="play_as"
                             value=Color::White.to_string()
-                            on:change=change_player
-                            prop:checked=move || play_as() == Some(Color::White)
+                            on:change=change_role.clone()
+                            prop:checked=move || role() == Some(Color::White)
                         />
                         <label for="play-as-white">"White"</label>
                     </div>
@@ -52,8 +123,8 @@ pub fn Play(cx: Scope) -> impl IntoView {
                             id="play-as-black"
                             name="play_as"
                             value=Color::Black.to_string()
-                            on:change=change_player
-                            prop:checked=move || play_as() == Some(Color::Black)
+                            on:change=change_role.clone()
+                            prop:checked=move || role() == Some(Color::Black)
                         />
                         <label for="play-as-black">"Black"</label>
                     </div>
@@ -62,14 +133,140 @@ pub fn Play(cx: Scope) -> impl IntoView {
                             type="radio"
                             id="play-as-none"
                             name="play_as"
-                            value="-"
-                            on:change=change_player
-                            prop:checked=move || play_as().is_none()
+                            value=SPECTATE
+                            on:change=change_role
+                            prop:checked=move || role().is_none()
                         />
                         <label for="play-as-none">"Spectate"</label>
                     </div>
                 </fieldset>
+                <label>
+                    <input
+                        type="checkbox"
+                        on:change=toggle_auto_queen
+                        prop:checked=move || auto_queen()
+                    />
+                    "Always promote to queen"
+                </label>
+                <a href=pgn_url download=true>"Download PGN"</a>
+            </div>
+        </>
+    }
+}
+
+/// The board as seen from `color`'s seat, with the game actions
+#[component]
+fn Seat(
+    cx: Scope,
+    id: String,
+    color: Color,
+    #[prop(into)] auto_queen: Signal<bool>,
+    set_position: WriteSignal<String>,
+) -> impl IntoView {
+    let Provider {
+        board,
+        connection,
+        last_move,
+        make_move,
+        offers,
+        act,
+    } = play_board(cx, id, color);
+    create_effect(cx, move |_| set_position(board.with(Position::to_fen)));
+    let opponent = match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    };
+    // Shows who made an offer, with buttons to answer the opponent's
+    let offer = move |made_by: fn(&Offers) -> Option<Color>,
+                      what: &'static str,
+                      accept: GameAction,
+                      decline: GameAction| {
+        move || match made_by(&offers()) {
+            Some(c) if c == opponent => Some(view! {
+                cx,
+                <div class="offer">
+                    {format!("Your opponent offers a {what}")}
+                    <button on:click=move |_| act.set(accept)>"Accept"</button>
+                    <button on:click=move |_| act.set(decline)>"Decline"</button>
+                </div>
+            }),
+            Some(_) => Some(view! {
+                cx,
+                <div class="offer">{format!("You offered a {what}")}</div>
+            }),
+            None => None,
+        }
+    };
+    let status = move || match connection() {
+        Connection::Connecting => Some("Connecting..."),
+        Connection::Connected => None,
+        Connection::Refused => Some("This seat is taken"),
+        Connection::Restarting => Some("The server is restarting. Reload to rejoin."),
+        Connection::Closed => Some("Disconnected"),
+    };
+
+    view! {
+        cx,
+        <>
+            <ChessBoard
+                board=board
+                make_move=make_move
+                play_as=Some(color)
+                view_as=color
+                auto_queen=auto_queen
+                last_move=last_move
+            />
+            <p class="connection">{status}</p>
+            <div class="game-actions">
+                <button on:click=move |_| act.set(GameAction::Resign)>"Resign"</button>
+                <button on:click=move |_| act.set(GameAction::OfferDraw)>"Offer draw"</button>
+                <button on:click=move |_| act.set(GameAction::RequestTakeback)>"Takeback"</button>
+                <button on:click=move |_| act.set(GameAction::Abort)>"Abort"</button>
             </div>
+            {offer(|o| o.draw, "draw", GameAction::AcceptDraw, GameAction::DeclineDraw)}
+            {offer(
+                |o| o.takeback,
+                "takeback",
+                GameAction::AcceptTakeback,
+                GameAction::DeclineTakeback,
+            )}
         </>
     }
 }
+
+/// The board as seen by someone watching the game
+#[component]
+fn Spectator(cx: Scope, id: String, set_position: WriteSignal<String>) -> impl IntoView {
+    let Provider {
+        board,
+        connection,
+        last_move,
+        make_move,
+        ..
+    } = spectate_board(cx, id);
+    create_effect(cx, move |_| set_position(board.with(Position::to_fen)));
+    let status = move || match connection() {
+        Connection::Connecting => Some("Connecting..."),
+        Connection::Connected => None,
+        Connection::Restarting => Some("The server is restarting. Reload to watch again."),
+        Connection::Refused | Connection::Closed => Some("Disconnected"),
+    };
+
+    view! {
+        cx,
+        <>
+            <ChessBoard
+                board=board
+                make_move=make_move
+                play_as=None
+                view_as=Color::White
+                last_move=last_move
+            />
+            <p class="connection">{status}</p>
+        </>
+    }
+}
+
+async fn fetch_players(url: String) -> Option<[Option<PlayerInfo>; 2]> {
+    Request::get(&url).send().await.ok()?.json().await.ok()
+}
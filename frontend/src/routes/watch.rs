@@ -0,0 +1,98 @@
+use api::listing::{BoardPage, BoardSummary};
+use chb_chess::Color;
+use gloo_net::http::Request;
+use leptos::*;
+use leptos_meta::{Title, TitleProps};
+use leptos_router::{AProps, A};
+
+use crate::board_provider::{spectate_board, Provider};
+use crate::chess_board::{ChessBoard, ChessBoardProps};
+use crate::routes::play::SPECTATE;
+
+/// Ongoing games, each with a small board that follows along
+#[component]
+pub fn Watch(cx: Scope) -> impl IntoView {
+    let (page, set_page) = create_signal(cx, 0);
+    let boards = create_local_resource(cx, move || page(), fetch_page);
+    let last_page = move || {
+        boards
+            .read(cx)
+            .flatten()
+            .map_or(0, |p| p.total.saturating_sub(1) / p.per_page)
+    };
+
+    view! {
+        cx,
+        <>
+            <Title text="Watch"/>
+            <div class="content">
+                <h1>"Live games"</h1>
+                <Suspense fallback=move || view! { cx, <p>"Loading..."</p>}>
+                    <div class="live-boards">
+                        {move || {
+                            boards
+                                .read(cx)
+                                .flatten()
+                                .map(|p| {
+                                    p.boards
+                                        .into_iter()
+                                        .map(|summary| view! { cx, <LiveBoard summary/> })
+                                        .collect::<Vec<_>>()
+                                })
+                        }}
+                    </div>
+                </Suspense>
+                <button
+                    on:click=move |_| set_page.update(|p| *p = p.saturating_sub(1))
+                    prop:disabled=move || page() == 0
+                >
+                    "Previous"
+                </button>
+                <button
+                    on:click=move |_| set_page.update(|p| *p += 1)
+                    prop:disabled=move || page() >= last_page()
+                >
+                    "Next"
+                </button>
+            </div>
+        </>
+    }
+}
+
+#[component]
+fn LiveBoard(cx: Scope, summary: BoardSummary) -> impl IntoView {
+    let Provider {
+        board,
+        last_move,
+        make_move,
+        ..
+    } = spectate_board(cx, summary.id.clone());
+    let name = |name: Option<String>| name.unwrap_or("Waiting for player".to_owned());
+    let [white, black] = summary.names;
+    let time_control = summary
+        .time_control
+        .map_or("Untimed".to_owned(), |tc| tc.to_string());
+
+    view! {
+        cx,
+        <div class="live-board">
+            <A href=format!("/play/{}?as={SPECTATE}", summary.id)>
+                <p>{name(black)}</p>
+                <ChessBoard
+                    board=board
+                    make_move=make_move
+                    play_as=None
+                    view_as=Color::White
+                    last_move=last_move
+                />
+                <p>{name(white)}</p>
+                <p>{time_control} {summary.rated.then_some(" rated")}</p>
+            </A>
+        </div>
+    }
+}
+
+async fn fetch_page(page: usize) -> Option<BoardPage> {
+    let url = format!("/api/boards?status=active&page={page}");
+    Request::get(&url).send().await.ok()?.json().await.ok()
+}
@@ -0,0 +1,42 @@
+use leptos::document;
+
+/// Name of the meta tag the server uses to override where sockets connect
+pub const ORIGIN_META: &str = "ws-origin";
+
+/// WebSocket URL for the server's `path`, which starts with a slash.
+///
+/// Connects to wherever the page came from, using wss for pages served over https and keeping
+/// the path of any `<base>` element, unless the server set an origin in the `ws-origin` meta tag.
+/// Reads the document, so it is only for code running in the browser, like effects. `None` if
+/// there is no page to go by.
+pub fn socket_url(path: &str) -> Option<String> {
+    let origin = configured_origin().or_else(page_origin)?;
+    Some(format!("{}{path}", origin.trim_end_matches('/')))
+}
+
+fn configured_origin() -> Option<String> {
+    let meta = document()
+        .query_selector(&format!("meta[name='{ORIGIN_META}']"))
+        .ok()??;
+    meta.get_attribute("content").filter(|c| !c.is_empty())
+}
+
+fn page_origin() -> Option<String> {
+    let location = document().location()?;
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws",
+    };
+    let host = location.host().unwrap_or_default();
+    // Only a <base> element moves the app away from the root
+    let has_base = matches!(document().query_selector("base"), Ok(Some(_)));
+    let base_path = document()
+        .base_uri()
+        .ok()
+        .flatten()
+        .filter(|_| has_base)
+        .and_then(|uri| web_sys::Url::new(&uri).ok())
+        .map(|url| url.pathname())
+        .unwrap_or_default();
+    Some(format!("{scheme}://{host}{base_path}"))
+}
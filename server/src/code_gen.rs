@@ -1,14 +1,26 @@
-use rand::{thread_rng, Rng};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 
 const CODE_CHARS: [char; 31] = [
     'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'J', 'K', 'M', 'N', 'P', 'Q', 'R', 'S', 'T', 'U', 'V',
     'W', 'X', 'Y', 'Z', '2', '3', '4', '5', '6', '7', '8', '9',
 ];
 
-pub fn get_code() -> String {
+/// Length of game codes unless configured otherwise, and of guest ids
+pub const DEFAULT_CODE_LENGTH: usize = 6;
+
+pub fn get_code(length: usize) -> String {
     let mut rng = thread_rng();
     let mut next = move || rng.gen_range(0..CODE_CHARS.len());
-    (0..6)
+    (0..length)
         .map(|_| CODE_CHARS.get(next()).expect("RNG Code gen out of range"))
         .collect()
 }
+
+/// Secret for reclaiming a seat
+pub fn get_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::code_gen::DEFAULT_CODE_LENGTH;
+
+/// Read when no config file is named, if it exists
+const DEFAULT_CONFIG_FILE: &str = "web_chess.toml";
+const DATABASE_FILE: &str = "web_chess.db";
+/// Shorter codes are too easy to guess
+const CODE_LENGTHS: std::ops::RangeInclusive<usize> = 4..=32;
+
+/// One source of settings. Each setting comes from the command line, then the environment, then
+/// the config file, using the first that has it.
+#[derive(Parser, Debug, Default, Deserialize)]
+#[command(about = "Serves the chess website")]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Layer {
+    /// TOML file to read settings from, named like the flags
+    #[arg(long, env = "WEB_CHESS_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// Most detailed log messages shown: off, error, warn, info, debug or trace
+    #[arg(long, env = "WEB_CHESS_LOG_LEVEL")]
+    log_level: Option<String>,
+    /// Number of characters in new game codes
+    #[arg(long, env = "WEB_CHESS_CODE_LENGTH")]
+    code_length: Option<usize>,
+    /// Most unfinished games at once. Unlimited if not set.
+    #[arg(long, env = "WEB_CHESS_MAX_GAMES")]
+    max_games: Option<usize>,
+    /// Minutes before games nobody is connected to are dropped, aborting unfinished ones. Games
+    /// are kept if not set.
+    #[arg(long, env = "WEB_CHESS_IDLE_EXPIRY")]
+    idle_expiry: Option<u64>,
+    /// Directory holding the database
+    #[arg(long, env = "WEB_CHESS_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Directory the site is served from, instead of Leptos' site root
+    #[arg(long, env = "WEB_CHESS_SITE_ROOT")]
+    site_root: Option<String>,
+    /// Address to listen on, instead of Leptos' site address
+    #[arg(long, env = "WEB_CHESS_ADDR")]
+    addr: Option<SocketAddr>,
+    /// Where pages open sockets, like wss://example.com/chess, instead of where they were served
+    /// from
+    #[arg(long, env = "WEB_CHESS_WS_ORIGIN")]
+    ws_origin: Option<String>,
+}
+
+impl Layer {
+    fn read(path: &Path) -> Result<Layer> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Fills in anything this layer doesn't set from `lower`
+    fn or(self, lower: Layer) -> Layer {
+        Layer {
+            config: self.config.or(lower.config),
+            log_level: self.log_level.or(lower.log_level),
+            code_length: self.code_length.or(lower.code_length),
+            max_games: self.max_games.or(lower.max_games),
+            idle_expiry: self.idle_expiry.or(lower.idle_expiry),
+            data_dir: self.data_dir.or(lower.data_dir),
+            site_root: self.site_root.or(lower.site_root),
+            addr: self.addr.or(lower.addr),
+            ws_origin: self.ws_origin.or(lower.ws_origin),
+        }
+    }
+}
+
+/// Server settings, checked at startup
+#[derive(Debug)]
+pub struct Settings {
+    pub log_level: LevelFilter,
+    pub code_length: usize,
+    pub max_games: Option<usize>,
+    pub idle_expiry: Option<Duration>,
+    pub data_dir: PathBuf,
+    /// Overrides for Leptos' configuration
+    pub site_root: Option<String>,
+    pub addr: Option<SocketAddr>,
+    pub ws_origin: Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings from the command line, environment and config file
+    pub fn load() -> Result<Settings> {
+        let args = Layer::parse();
+        let file = match &args.config {
+            Some(path) => Layer::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Layer::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Layer::default(),
+        };
+        Settings::validate(args.or(file))
+    }
+
+    fn validate(layer: Layer) -> Result<Settings> {
+        let log_level = match layer.log_level {
+            Some(level) => level
+                .parse()
+                .map_err(|_| anyhow!("invalid log level {level}"))?,
+            None => LevelFilter::Debug,
+        };
+        let code_length = layer.code_length.unwrap_or(DEFAULT_CODE_LENGTH);
+        if !CODE_LENGTHS.contains(&code_length) {
+            bail!(
+                "code length must be between {} and {}",
+                CODE_LENGTHS.start(),
+                CODE_LENGTHS.end()
+            );
+        }
+        if layer.max_games == Some(0) {
+            bail!("max games must be at least 1");
+        }
+        if layer.idle_expiry == Some(0) {
+            bail!("idle expiry must be at least 1 minute");
+        }
+        if let Some(origin) = &layer.ws_origin {
+            if !origin.starts_with("ws://") && !origin.starts_with("wss://") {
+                bail!("ws origin must start with ws:// or wss://");
+            }
+        }
+        let data_dir = layer.data_dir.unwrap_or_else(|| PathBuf::from("."));
+        fs::create_dir_all(&data_dir)
+            .with_context(|| format!("couldn't create data directory {}", data_dir.display()))?;
+        Ok(Settings {
+            log_level,
+            code_length,
+            max_games: layer.max_games,
+            idle_expiry: layer.idle_expiry.map(|m| Duration::from_secs(m * 60)),
+            data_dir,
+            site_root: layer.site_root,
+            addr: layer.addr,
+            ws_origin: layer.ws_origin,
+        })
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        self.data_dir.join(DATABASE_FILE)
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use log::info;
+
+use crate::BoardList;
+
+/// How often games are checked for having been left
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drops games nobody has been connected to for `expiry`, aborting any that haven't finished.
+/// They stay in storage, so finished games can still be looked at.
+pub async fn expire_idle_games(boards: BoardList, expiry: Duration) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL.min(expiry));
+    loop {
+        interval.tick().await;
+        // Collected first so the list isn't locked while waiting on each game
+        let games: Vec<_> = boards
+            .read()
+            .await
+            .iter()
+            .map(|(id, live)| (id.clone(), live.game.clone()))
+            .collect();
+        let mut expired = Vec::new();
+        for (id, game) in games {
+            let mut game = game.lock().await;
+            if game.idle_time().is_some_and(|idle| idle >= expiry) {
+                game.expire().await;
+                expired.push(id);
+            }
+        }
+        if expired.is_empty() {
+            continue;
+        }
+        let mut boards = boards.write().await;
+        for id in &expired {
+            boards.remove(id);
+        }
+        info!("Dropped {} idle games", expired.len());
+    }
+}
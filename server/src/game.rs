@@ -1,23 +1,149 @@
-use std::sync::Arc;
+use std::{collections::HashMap, mem, sync::Arc, time::Duration};
 
-use anyhow::{anyhow, Result};
-use chb_chess::{Board, Color, Move};
+use anyhow::{anyhow, bail, Result};
+use api::{
+    clock::{ClockState, TimeControl},
+    computer::ComputerSeat,
+    game::{GameAction, GameResult, History, HistoryEntry, Offers, Termination},
+    listing::GameStatus,
+    position::{position_hash, position_key, MoveUpdate, Position, Snapshot},
+    protocol::{MoveRejection, RejectReason},
+};
+use chb_chess::{Color, Move};
 use tokio::{
     sync::{
         broadcast::{self, Receiver, Sender},
-        Mutex,
+        oneshot, watch, Mutex,
     },
-    task,
+    task, time,
+};
+
+use crate::{
+    code_gen,
+    participant::Participant,
+    pgn,
+    storage::{self, GameRecord, GameSnapshot, StoredGame},
 };
 
-use crate::participant::Participant;
+use self::clock::Clock;
+
+pub mod clock;
+pub mod rules;
 
 pub type Player = Arc<Mutex<dyn Participant + Send + 'static>>;
 
+/// Events a spectator can fall behind by before having to resync
+const BROADCAST_CAPACITY: usize = 64;
+
+/// How long a disconnected player has to reclaim their seat
+const SEAT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
 pub struct Game {
-    board: Board,
+    board: Position,
     game_state: GameState,
-    broadcast: Sender<Move>,
+    broadcast: Sender<GameEvent>,
+    result: Option<GameResult>,
+    positions: HashMap<String, usize>,
+    clock: Option<Clock>,
+    start_fen: String,
+    time_control: Option<TimeControl>,
+    history: Vec<PlayedMove>,
+    names: [Option<String>; 2],
+    /// Whether the result counts towards the players' ratings
+    rated: bool,
+    /// Kept so the computer can be started again after a restart
+    computer: Option<ComputerSeat>,
+    record: Option<GameRecord>,
+    created_at: i64,
+    seats: [Option<Seat>; 2],
+    connections: u64,
+    /// Whether a task is running the turns, so there is never more than one
+    running: bool,
+    offers: Offers,
+    /// Wakes the turn task when something other than a move changes the game
+    interrupts: watch::Sender<()>,
+    /// Set when the game ended while the turn task was waiting on a player, so the players
+    /// still need to be told
+    result_unsent: bool,
+    /// When the last player left, if nobody is seated and connected
+    idle_since: Option<time::Instant>,
+    /// Kept up to date for [`LiveGame`]
+    summary: watch::Sender<Summary>,
+}
+
+/// What the game list shows of a game
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub status: GameStatus,
+    pub names: [Option<String>; 2],
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub fen: String,
+    pub result: Option<GameResult>,
+    pub created_at: i64,
+}
+
+/// A game on the board list, with a summary that can be read without waiting for the game
+#[derive(Clone)]
+pub struct LiveGame {
+    pub game: Arc<Mutex<Game>>,
+    pub summary: watch::Receiver<Summary>,
+}
+
+impl LiveGame {
+    pub fn new(game: Game) -> Self {
+        let summary = game.summary.subscribe();
+        Self {
+            game: Arc::new(Mutex::new(game)),
+            summary,
+        }
+    }
+}
+
+/// Ownership of one side of the board
+struct Seat {
+    /// Secret that lets the owner back in after disconnecting
+    token: String,
+    /// Identifies the owner's latest connection
+    connection: u64,
+    connected: bool,
+}
+
+/// A seat handed to a connecting player
+pub struct SeatClaim {
+    pub token: String,
+    pub connection: u64,
+}
+
+/// A move the game is waiting on
+pub struct Turn {
+    pub color: Color,
+    /// Number of moves made before this turn
+    pub ply: usize,
+    pub player: Player,
+    /// Time left before the player's flag falls, if the game is timed
+    pub allowance: Option<Duration>,
+    /// Changes if the turn is cut short, e.g. by a resignation or takeback
+    pub interrupted: watch::Receiver<()>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlayedMove {
+    pub mv: Move,
+    /// The clocks just after the move was made
+    pub clock: Option<ClockState>,
+}
+
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    Move(MoveUpdate),
+    Clock(ClockState),
+    End(GameResult),
+    Offers(Offers),
+    /// Moves were taken back, leaving the game at this position
+    TakeBack(Snapshot),
+    /// The server is shutting down
+    Shutdown,
 }
 
 #[derive(Clone)]
@@ -27,54 +153,533 @@ pub enum GameState {
 }
 
 impl Game {
-    pub fn new(board: Board) -> Game {
-        let (broadcast, _) = broadcast::channel(1);
+    pub fn new(board: Position, time_control: Option<TimeControl>) -> Game {
+        let (broadcast, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let mut positions = HashMap::new();
+        let start_fen = board.to_fen();
+        positions.insert(position_key(&start_fen), 1);
+        let result = rules::result(&board, 1);
+        let created_at = storage::now();
+        let summary = Summary {
+            status: match result {
+                Some(_) => GameStatus::Finished,
+                None => GameStatus::Setup,
+            },
+            names: [None, None],
+            time_control,
+            rated: false,
+            fen: start_fen.clone(),
+            result,
+            created_at,
+        };
         Game {
+            result,
             board,
             broadcast,
             game_state: GameState::Setup([None, None]),
+            positions,
+            clock: time_control.map(Clock::new),
+            start_fen,
+            time_control,
+            history: Vec::new(),
+            names: [None, None],
+            rated: false,
+            computer: None,
+            record: None,
+            created_at,
+            seats: [None, None],
+            connections: 0,
+            running: false,
+            offers: Offers::default(),
+            interrupts: watch::channel(()).0,
+            result_unsent: false,
+            idle_since: Some(time::Instant::now()),
+            summary: watch::channel(summary).0,
         }
     }
 
-    pub async fn do_turn(&mut self) -> Result<()> {
-        if !self.is_active() {
+    /// Makes a move that has already been played, e.g. when loading a game. Unlike a turn,
+    /// nobody is notified.
+    pub fn replay(&mut self, mv: Move, clock: Option<ClockState>) -> Result<()> {
+        self.board
+            .make(mv)
+            .map_err(|_| anyhow!("Illegal move {mv} in replayed game"))?;
+        self.history.push(PlayedMove { mv, clock });
+        let repetitions = self
+            .positions
+            .entry(position_key(&self.board.to_fen()))
+            .or_default();
+        *repetitions += 1;
+        self.result = rules::result(&self.board, *repetitions);
+        self.update_summary();
+        Ok(())
+    }
+
+    /// Rebuilds a game from storage by replaying its moves
+    pub fn restore(stored: StoredGame) -> Result<Game> {
+        let board = stored
+            .start_fen
+            .parse::<Position>()
+            .map_err(|_| anyhow!("Stored game {} has an invalid start", stored.id))?;
+        let mut game = Game::new(board, stored.time_control);
+        for m in &stored.moves {
+            game.replay(m.mv, m.clock)?;
+        }
+        let last_clock = stored.moves.last().and_then(|m| m.clock);
+        if let (Some(control), Some(state)) = (stored.time_control, last_clock) {
+            game.clock = Some(Clock::resume(control, state));
+        }
+        game.names = stored.names;
+        game.rated = stored.rated;
+        game.computer = stored.computer;
+        game.result = stored.result;
+        game.created_at = stored.created_at;
+        game.update_summary();
+        Ok(game)
+    }
+
+    /// Saves the game under `id` from now on. Only needed for new games.
+    pub fn persist(&mut self, record: GameRecord) {
+        record.create(
+            &self.start_fen,
+            self.time_control,
+            self.rated,
+            self.computer.as_ref(),
+        );
+        for (i, played) in self.history.iter().enumerate() {
+            record.record_move(i + 1, played.mv, played.clock);
+        }
+        for color in [Color::White, Color::Black] {
+            if let Some(name) = &self.names[color] {
+                record.set_name(color, name);
+            }
+        }
+        self.record = Some(record);
+    }
+
+    /// Reattaches storage to a restored game
+    pub fn resume_record(&mut self, record: GameRecord) {
+        self.record = Some(record);
+    }
+
+    /// Stops the game for a server shutdown, telling everyone connected. Returns what's needed
+    /// to pick it up where it was after the restart.
+    pub fn suspend(&mut self) -> GameSnapshot {
+        if let Some(clock) = &mut self.clock {
+            clock.pause();
+        }
+        _ = self.broadcast.send(GameEvent::Shutdown);
+        GameSnapshot {
+            clock: self.clock.as_ref().map(Clock::state),
+            tokens: [Color::White, Color::Black]
+                .map(|color| self.seats[color].as_ref().map(|seat| seat.token.clone())),
+        }
+    }
+
+    /// Puts back the clocks and seats of a game suspended at shutdown. The seats are kept for
+    /// their owners, who get back in with their tokens. Returns the seats and connections to
+    /// hold with [`ExecExt::hold_seat`].
+    pub fn resume_snapshot(&mut self, snapshot: GameSnapshot) -> Vec<(Color, u64)> {
+        let mut held = Vec::new();
+        if let (Some(control), Some(state)) = (self.time_control, snapshot.clock) {
+            self.clock = Some(Clock::resume(control, state));
+        }
+        for (color, token) in [Color::White, Color::Black].into_iter().zip(snapshot.tokens) {
+            let Some(token) = token else {
+                continue;
+            };
+            self.connections += 1;
+            self.seats[color] = Some(Seat {
+                token,
+                connection: self.connections,
+                connected: false,
+            });
+            held.push((color, self.connections));
+        }
+        held
+    }
+
+    /// Seats `player` as the computer in `seat`, claiming the seat so nobody can join in its
+    /// place
+    pub fn seat_computer(&mut self, seat: ComputerSeat, player: Player) {
+        self.seats[seat.color] = None;
+        _ = self.claim_seat(seat.color, None);
+        self.set_player(seat.color, Some(player));
+        self.computer = Some(seat);
+    }
+
+    pub fn computer(&self) -> Option<&ComputerSeat> {
+        self.computer.as_ref()
+    }
+
+    /// Starts the next turn, returning who needs to move. The game should not be locked while
+    /// waiting for the move, so spectators and other requests can still get to it.
+    pub fn next_turn(&mut self) -> Result<Turn> {
+        let GameState::Active(players) = &self.game_state else {
             return Err(anyhow!("Cannot do turn in inactive state"));
+        };
+        if self.is_finished() {
+            return Err(anyhow!("Cannot do turn in finished game"));
+        }
+        let color = self.board.color_to_move();
+        let player = players[color].clone();
+        let allowance = self.clock.as_mut().map(|c| {
+            c.start(color);
+            c.allowance(color)
+        });
+        Ok(Turn {
+            color,
+            ply: self.history.len(),
+            player,
+            allowance,
+            interrupted: self.interrupts.subscribe(),
+        })
+    }
+
+    /// True if `turn` is still the one being waited on, by the same player
+    fn is_current(&self, turn: &Turn) -> bool {
+        let GameState::Active(players) = &self.game_state else {
+            return false;
+        };
+        !self.is_finished()
+            && self.history.len() == turn.ply
+            && self.board.color_to_move() == turn.color
+            && Arc::ptr_eq(&players[turn.color], &turn.player)
+    }
+
+    /// Whether `color` could be claimed with `token` right now
+    pub fn can_claim(&self, color: Color, token: Option<&str>) -> bool {
+        match &self.seats[color] {
+            None => true,
+            Some(seat) => token == Some(seat.token.as_str()),
+        }
+    }
+
+    /// Takes the `color` seat. Free seats get a new token; taken ones need the owner's.
+    pub fn claim_seat(&mut self, color: Color, token: Option<&str>) -> Result<SeatClaim> {
+        if !self.can_claim(color, token) {
+            return Err(anyhow!("Seat is taken"));
+        }
+        // The turn task may be waiting on the connection this replaces
+        if self.seats[color].as_ref().is_some_and(|seat| seat.connected) {
+            self.interrupts.send_replace(());
+        }
+        self.connections += 1;
+        let connection = self.connections;
+        let seat = self.seats[color].get_or_insert_with(|| Seat {
+            token: code_gen::get_token(),
+            connection,
+            connected: true,
+        });
+        seat.connection = connection;
+        seat.connected = true;
+        self.idle_since = None;
+        Ok(SeatClaim {
+            token: seat.token.clone(),
+            connection,
+        })
+    }
+
+    /// Unseats `color` if `connection` is still theirs, returning whether it was
+    pub fn disconnect(&mut self, color: Color, connection: u64) -> bool {
+        match &mut self.seats[color] {
+            Some(seat) if seat.connection == connection && seat.connected => {
+                seat.connected = false;
+                self.set_player(color, None);
+                if !self.seats.iter().flatten().any(|seat| seat.connected) {
+                    self.idle_since = Some(time::Instant::now());
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Called once the grace period after `connection` dropped is over. If the owner hasn't
+    /// come back, they lose a game in progress or give up the seat if nothing was played.
+    pub async fn expire_seat(&mut self, color: Color, connection: u64) {
+        let abandoned = matches!(
+            &self.seats[color],
+            Some(seat) if seat.connection == connection && !seat.connected
+        );
+        if !abandoned || self.is_finished() {
+            return;
         }
-        let mut mv = self.get_next_move().await?;
-        while self.board.make(mv).is_err() {
-            mv = self.get_next_move().await?;
+        if self.history.is_empty() {
+            self.seats[color] = None;
+        } else {
+            let result = GameResult::win(rules::opponent(color), Termination::Abandoned);
+            self.conclude(result).await;
         }
-        self.broadcast.send(mv)?;
+    }
+
+    /// How long it has been since a player was connected, if none are now
+    pub fn idle_time(&self) -> Option<Duration> {
+        self.idle_since.map(|since| since.elapsed())
+    }
+
+    /// Aborts the game if it hasn't finished, for when it has been left for too long
+    pub async fn expire(&mut self) {
+        if !self.is_finished() {
+            self.conclude(GameResult::aborted()).await;
+        }
+    }
+
+    /// Handles an action from `color`'s player. Errors say why the action isn't allowed.
+    pub async fn act(&mut self, color: Color, action: GameAction) -> Result<()> {
+        if self.is_finished() {
+            bail!("The game is over");
+        }
+        let opponent = rules::opponent(color);
+        let offers = self.offers;
+        match action {
+            GameAction::Resign => {
+                let result = GameResult::win(opponent, Termination::Resignation);
+                self.conclude(result).await;
+            }
+            GameAction::Abort if !self.history.is_empty() => bail!("Too late to abort"),
+            GameAction::Abort => self.conclude(GameResult::aborted()).await,
+            GameAction::OfferDraw | GameAction::AcceptDraw if offers.draw == Some(opponent) => {
+                self.conclude(GameResult::draw(Termination::Agreement)).await;
+            }
+            GameAction::OfferDraw => self.set_offers(Offers {
+                draw: Some(color),
+                ..offers
+            }),
+            GameAction::DeclineDraw if offers.draw == Some(opponent) => self.set_offers(Offers {
+                draw: None,
+                ..offers
+            }),
+            GameAction::AcceptDraw | GameAction::DeclineDraw => bail!("No draw offer to answer"),
+            GameAction::RequestTakeback if self.takeback_plies(color) > self.history.len() => {
+                bail!("Nothing to take back")
+            }
+            GameAction::RequestTakeback => self.set_offers(Offers {
+                takeback: Some(color),
+                ..offers
+            }),
+            GameAction::AcceptTakeback if offers.takeback == Some(opponent) => {
+                self.take_back(self.takeback_plies(opponent))?;
+            }
+            GameAction::DeclineTakeback if offers.takeback == Some(opponent) => {
+                self.set_offers(Offers {
+                    takeback: None,
+                    ..offers
+                })
+            }
+            GameAction::AcceptTakeback | GameAction::DeclineTakeback => {
+                bail!("No takeback request to answer")
+            }
+        }
+        Ok(())
+    }
+
+    /// Plies to undo so it is `color`'s move again after their last one
+    fn takeback_plies(&self, color: Color) -> usize {
+        if self.board.color_to_move() == color {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Undoes the last `plies` moves by replaying the rest from the start
+    fn take_back(&mut self, plies: usize) -> Result<()> {
+        let keep = self
+            .history
+            .len()
+            .checked_sub(plies)
+            .ok_or(anyhow!("Nothing to take back"))?;
+        let board = self
+            .start_fen
+            .parse::<Position>()
+            .map_err(|_| anyhow!("Invalid starting position"))?;
+        let history = self.history[..keep].to_vec();
+        self.board = board;
+        self.history.clear();
+        self.positions = HashMap::from([(position_key(&self.start_fen), 1)]);
+        for played in history {
+            self.replay(played.mv, played.clock)?;
+        }
+        if let Some(clock) = &mut self.clock {
+            clock.pause();
+        }
+        if let Some(record) = &self.record {
+            record.take_back(keep);
+        }
+        self.update_summary();
+        _ = self.broadcast.send(GameEvent::TakeBack(self.snapshot()));
+        self.set_offers(Offers::default());
+        // The player waiting to move may not be the one to move anymore
+        self.interrupts.send_replace(());
+        Ok(())
+    }
+
+    fn set_offers(&mut self, offers: Offers) {
+        if self.offers != offers {
+            self.offers = offers;
+            _ = self.broadcast.send(GameEvent::Offers(offers));
+        }
+    }
+
+    /// Plays the move made during `turn`, rejecting it if it is illegal
+    pub async fn play(&mut self, turn: &Turn, mv: Move) -> Result<()> {
+        if !self.is_current(turn) {
+            return Err(anyhow!("Turn is over"));
+        }
+        if self.board.make(mv).is_err() {
+            let rejection = MoveRejection {
+                text: mv.to_string(),
+                reason: RejectReason::IllegalMove,
+            };
+            return turn.player.lock().await.reject_move(rejection).await;
+        }
+        let color = turn.color;
+        let in_time = self.clock.as_mut().map_or(true, |c| c.stop(color));
+        let clock = self.clock.as_ref().map(Clock::state);
+        self.history.push(PlayedMove { mv, clock });
+        if let Some(record) = &self.record {
+            record.record_move(self.history.len(), mv, clock);
+        }
+        self.update_summary();
+        // Moving answers any offers
+        self.set_offers(Offers::default());
+
+        // No receivers just means nobody is spectating
+        _ = self.broadcast.send(GameEvent::Move(MoveUpdate {
+            mv,
+            ply: self.history.len(),
+            hash: position_hash(&self.board),
+        }));
         let GameState::Active(players) = &self.game_state else {
             unreachable!("Game is active");
         };
         for color in [Color::White, Color::Black] {
             if players[color].lock().await.send_move(mv).await.is_err() {
-                self.set_player(Color::White, None);
+                self.set_player(color, None);
                 break;
             }
         }
+        if !in_time {
+            // The move arrived just as the flag fell
+            self.flag(color).await;
+            return Ok(());
+        }
+        self.update_result().await;
+        let (finished, next) = (self.is_finished(), self.board.color_to_move());
+        if let Some(clock) = &mut self.clock {
+            if !finished {
+                clock.start(next);
+            }
+            let state = clock.state();
+            self.send_clock(state).await;
+        }
         Ok(())
     }
 
-    pub async fn get_next_move(&self) -> Result<Move> {
-        match &self.game_state {
-            GameState::Active(players) => {
-                players[self.board.color_to_move()]
-                    .lock()
-                    .await
-                    .get_move()
-                    .await
+    /// Ends the game if `turn`'s player ran out of time before moving
+    pub async fn time_out(&mut self, turn: &Turn) {
+        if self.is_current(turn) {
+            self.flag(turn.color).await;
+        }
+    }
+
+    /// Unseats `turn`'s player after their connection failed
+    pub fn abandon(&mut self, turn: &Turn) {
+        if self.is_current(turn) {
+            self.set_player(turn.color, None);
+        }
+    }
+
+    /// Ends the game because `color` ran out of time
+    async fn flag(&mut self, color: Color) {
+        if let Some(clock) = &mut self.clock {
+            clock.flag(color);
+            let state = clock.state();
+            self.send_clock(state).await;
+        }
+        let opponent = rules::opponent(color);
+        let result = if rules::has_mating_material(&self.board, opponent) {
+            GameResult::win(opponent, Termination::Timeout)
+        } else {
+            GameResult::draw(Termination::TimeoutVsInsufficientMaterial)
+        };
+        self.end(result).await;
+    }
+
+    async fn send_clock(&self, state: ClockState) {
+        _ = self.broadcast.send(GameEvent::Clock(state));
+        if let GameState::Active(players) = &self.game_state {
+            for player in players {
+                _ = player.lock().await.send_clock(state).await;
             }
-            GameState::Setup(_) => Err(anyhow!("Cannot get move for inactive game")),
         }
     }
 
-    pub fn watch(&self) -> Receiver<Move> {
+    /// Checks whether the last move ended the game, notifying everyone if it did
+    async fn update_result(&mut self) {
+        let repetitions = self
+            .positions
+            .entry(position_key(&self.board.to_fen()))
+            .or_default();
+        *repetitions += 1;
+        if let Some(result) = rules::result(&self.board, *repetitions) {
+            self.end(result).await;
+        }
+    }
+
+    /// Ends the game from the turn task
+    async fn end(&mut self, result: GameResult) {
+        self.finish(result);
+        self.send_result(result).await;
+    }
+
+    /// Ends the game from outside the turn task. If it is running, it is likely waiting on a
+    /// player, who can't be told until the wait is interrupted.
+    async fn conclude(&mut self, result: GameResult) {
+        self.finish(result);
+        if self.running {
+            self.result_unsent = true;
+            self.interrupts.send_replace(());
+        } else {
+            self.send_result(result).await;
+        }
+    }
+
+    fn finish(&mut self, result: GameResult) {
+        self.result = Some(result);
+        self.offers = Offers::default();
+        if let Some(record) = &self.record {
+            record.finish(result);
+        }
+        self.update_summary();
+        _ = self.broadcast.send(GameEvent::End(result));
+    }
+
+    async fn send_result(&self, result: GameResult) {
+        if let GameState::Active(players) = &self.game_state {
+            for player in players {
+                // The game is over, so a disconnected player no longer matters
+                _ = player.lock().await.send_result(result).await;
+            }
+        }
+    }
+
+    /// Tells the players about a result from [`Game::conclude`], once the turn task is no
+    /// longer waiting on one of them
+    async fn send_unsent_result(&mut self) {
+        if std::mem::take(&mut self.result_unsent) {
+            if let Some(result) = self.result {
+                self.send_result(result).await;
+            }
+        }
+    }
+
+    pub fn watch(&self) -> Receiver<GameEvent> {
         self.broadcast.subscribe()
     }
 
-    pub fn board(&self) -> &Board {
+    pub fn board(&self) -> &Position {
         &self.board
     }
 
@@ -82,20 +687,143 @@ impl Game {
         self.board.to_fen()
     }
 
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            fen: self.fen(),
+            ply: self.history.len(),
+        }
+    }
+
+    pub fn offers(&self) -> Offers {
+        self.offers
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.result
+    }
+
+    pub fn clock(&self) -> Option<ClockState> {
+        self.clock.as_ref().map(Clock::state)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    pub fn start_fen(&self) -> &str {
+        &self.start_fen
+    }
+
+    pub fn time_control(&self) -> Option<TimeControl> {
+        self.time_control
+    }
+
+    /// Every move made since the start, in order
+    pub fn history(&self) -> &[PlayedMove] {
+        &self.history
+    }
+
+    /// The history as sent to clients, with moves in SAN
+    pub fn history_info(&self) -> Result<History> {
+        let mut board = self
+            .start_fen
+            .parse::<Position>()
+            .map_err(|_| anyhow!("Invalid starting position"))?;
+        let mut moves = Vec::with_capacity(self.history.len());
+        for played in &self.history {
+            moves.push(HistoryEntry {
+                mv: played.mv,
+                san: pgn::san(&board, played.mv),
+                clock: played.clock,
+            });
+            board
+                .make(played.mv)
+                .map_err(|_| anyhow!("Illegal move in game history"))?;
+        }
+        Ok(History {
+            start_fen: self.start_fen.clone(),
+            moves,
+        })
+    }
+
+    /// Unix timestamp in seconds
+    pub fn created_at(&self) -> i64 {
+        self.created_at
+    }
+
+    pub fn names(&self) -> &[Option<String>; 2] {
+        &self.names
+    }
+
+    pub fn is_rated(&self) -> bool {
+        self.rated
+    }
+
+    /// Only has an effect before the game is persisted
+    pub fn set_rated(&mut self, rated: bool) {
+        self.rated = rated;
+        self.update_summary();
+    }
+
+    pub fn set_name(&mut self, color: Color, name: String) {
+        if let Some(record) = &self.record {
+            record.set_name(color, &name);
+        }
+        self.names[color] = Some(name);
+        self.update_summary();
+    }
+
+    /// Seats `player` as `color`. A player it replaces, like an older connection to the same
+    /// seat, is closed.
     pub fn set_player(&mut self, color: Color, player: Option<Player>) {
-        match (&mut self.game_state, player) {
-            (GameState::Setup(players), p @ _) => players[color] = p,
-            (GameState::Active(players), Some(p)) => players[color] = p,
+        let new = player.clone();
+        let old = match (&mut self.game_state, player) {
+            (GameState::Setup(players), p) => mem::replace(&mut players[color], p),
+            (GameState::Active(players), Some(p)) => Some(mem::replace(&mut players[color], p)),
             (GameState::Active(_), None) => {
                 self.into_setup();
                 self.set_player(color, None);
+                None
+            }
+        };
+        if let (Some(old), Some(new)) = (old, new) {
+            if !Arc::ptr_eq(&old, &new) {
+                // The turn task lets go of the old player once interrupted
+                self.interrupts.send_replace(());
+                task::spawn(async move {
+                    _ = old.lock().await.close().await;
+                });
             }
         }
-        _ = self.into_active()
+        _ = self.into_active();
+        self.update_summary();
+    }
+
+    fn update_summary(&self) {
+        self.summary.send_replace(Summary {
+            status: self.status(),
+            names: self.names.clone(),
+            time_control: self.time_control,
+            rated: self.rated,
+            fen: self.fen(),
+            result: self.result,
+            created_at: self.created_at,
+        });
+    }
+
+    /// Games that have started count as active even while a player is reconnecting
+    pub fn status(&self) -> GameStatus {
+        if self.is_finished() {
+            GameStatus::Finished
+        } else if self.is_active() || !self.history.is_empty() {
+            GameStatus::Active
+        } else {
+            GameStatus::Setup
+        }
     }
 
     pub fn is_active(&self) -> bool {
-        matches!(self.game_state, GameState::Active(_))
+        matches!(self.game_state, GameState::Active(_)) && !self.is_finished()
     }
 
     fn into_active(&mut self) -> Result<()> {
@@ -116,7 +844,9 @@ impl Game {
         match self.game_state.clone() {
             GameState::Active([white, black]) => {
                 // notify players/spectators that game is pausing
-                self.game_state = GameState::Setup([Some(white), Some(black)])
+                self.game_state = GameState::Setup([Some(white), Some(black)]);
+                // Stops the turn task waiting on a player who may be gone
+                self.interrupts.send_replace(());
             }
             GameState::Setup(_) => (),
         }
@@ -125,22 +855,101 @@ impl Game {
 
 pub trait ExecExt {
     fn start(self);
+    /// Frees `color`'s seat when `closed` fires, unless they reconnect in time
+    fn watch_seat(self, color: Color, connection: u64, closed: oneshot::Receiver<()>);
+    /// Keeps a seat claimed for its disconnected owner for the grace period, then frees it or
+    /// ends the game as [`Game::expire_seat`] does
+    fn hold_seat(self, color: Color, connection: u64);
 }
 
 impl ExecExt for Arc<Mutex<Game>> {
     fn start(self) {
         task::spawn(async move {
+            {
+                let mut g = self.lock().await;
+                if g.running {
+                    return;
+                }
+                g.running = true;
+            }
             loop {
+                let turn = {
+                    let mut g = self.lock().await;
+                    g.send_unsent_result().await;
+                    match g.next_turn() {
+                        Ok(turn) => turn,
+                        Err(_) => {
+                            g.running = false;
+                            break;
+                        }
+                    }
+                };
+                // Wait for the move without holding the game
+                let mv = {
+                    let mut interrupted = turn.interrupted.clone();
+                    let mut player = turn.player.lock().await;
+                    let get_move = async {
+                        match turn.allowance {
+                            Some(allowance) => {
+                                time::timeout(allowance, player.get_move()).await.ok()
+                            }
+                            None => Some(player.get_move().await),
+                        }
+                    };
+                    tokio::select! {
+                        mv = get_move => mv,
+                        // Start over with whatever the game looks like now
+                        _ = interrupted.changed() => continue,
+                    }
+                };
                 let mut g = self.lock().await;
-                if g.is_active() {
-                    if g.do_turn().await.is_err() {}
-                } else {
-                    break;
+                match mv {
+                    Some(Ok(mv)) => {
+                        _ = g.play(&turn, mv).await;
+                    }
+                    Some(Err(_)) => g.abandon(&turn),
+                    None => g.time_out(&turn).await,
                 }
-                drop(g);
-                // need to yield time when not holding a mutable reference to the game
-                task::yield_now().await;
             }
         });
     }
+
+    fn watch_seat(self, color: Color, connection: u64, closed: oneshot::Receiver<()>) {
+        task::spawn(async move {
+            _ = closed.await;
+            if self.lock().await.disconnect(color, connection) {
+                self.hold_seat(color, connection);
+            }
+        });
+    }
+
+    fn hold_seat(self, color: Color, connection: u64) {
+        task::spawn(async move {
+            time::sleep(SEAT_GRACE_PERIOD).await;
+            self.lock().await.expire_seat(color, connection).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn restored_seat_is_freed_after_grace_period() {
+        let mut game = Game::new(Position::default(), None);
+        let held = game.resume_snapshot(GameSnapshot {
+            clock: None,
+            tokens: [Some("token".to_string()), None],
+        });
+        let game = Arc::new(Mutex::new(game));
+        for (color, connection) in held {
+            game.clone().hold_seat(color, connection);
+        }
+
+        time::sleep(SEAT_GRACE_PERIOD / 2).await;
+        assert!(!game.lock().await.can_claim(Color::White, None));
+        time::sleep(SEAT_GRACE_PERIOD).await;
+        assert!(game.lock().await.can_claim(Color::White, None));
+    }
 }
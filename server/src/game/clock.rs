@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use api::clock::{Bonus, ClockState, TimeControl};
+use chb_chess::Color;
+use tokio::time::Instant;
+
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    turn_start: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining: [control.base(); 2],
+            turn_start: None,
+        }
+    }
+
+    /// Picks up a clock that was stopped at `state`
+    pub fn resume(control: TimeControl, state: ClockState) -> Self {
+        Self {
+            control,
+            remaining: [
+                state.remaining(Color::White),
+                state.remaining(Color::Black),
+            ],
+            turn_start: None,
+        }
+    }
+
+    /// Starts `color`'s clock. Does nothing if it is already running.
+    pub fn start(&mut self, color: Color) {
+        if !matches!(self.turn_start, Some((c, _)) if c == color) {
+            self.turn_start = Some((color, Instant::now()));
+        }
+    }
+
+    /// Time `color` has left before their flag falls, including any delay
+    pub fn allowance(&self, color: Color) -> Duration {
+        let delay = match self.control.bonus {
+            Bonus::Delay(d) => Duration::from_millis(d),
+            _ => Duration::ZERO,
+        };
+        (self.remaining[color] + delay).saturating_sub(self.elapsed(color))
+    }
+
+    /// Stops the running clock after `color` made a move, applying the bonus. Returns false if
+    /// `color` ran out of time before moving.
+    pub fn stop(&mut self, color: Color) -> bool {
+        let used = self.elapsed(color);
+        self.turn_start = None;
+        let (used, bonus) = match self.control.bonus {
+            Bonus::None => (used, Duration::ZERO),
+            Bonus::Increment(i) => (used, Duration::from_millis(i)),
+            Bonus::Bronstein(d) => (used, used.min(Duration::from_millis(d))),
+            Bonus::Delay(d) => (used.saturating_sub(Duration::from_millis(d)), Duration::ZERO),
+        };
+        if used > self.remaining[color] {
+            self.remaining[color] = Duration::ZERO;
+            return false;
+        }
+        self.remaining[color] = self.remaining[color] - used + bonus;
+        true
+    }
+
+    /// Stops the running clock without a bonus, e.g. when the position changes under it
+    pub fn pause(&mut self) {
+        if let Some((color, _)) = self.turn_start {
+            self.remaining[color] = self.remaining[color].saturating_sub(self.elapsed(color));
+            self.turn_start = None;
+        }
+    }
+
+    /// Flags `color`, leaving them with no time
+    pub fn flag(&mut self, color: Color) {
+        self.turn_start = None;
+        self.remaining[color] = Duration::ZERO;
+    }
+
+    pub fn state(&self) -> ClockState {
+        let left = |color| {
+            self.remaining[color]
+                .saturating_sub(self.elapsed(color))
+                .as_millis() as u64
+        };
+        ClockState {
+            white: left(Color::White),
+            black: left(Color::Black),
+            running: self.turn_start.map(|(c, _)| c),
+        }
+    }
+
+    fn elapsed(&self, color: Color) -> Duration {
+        match self.turn_start {
+            Some((c, start)) if c == color => start.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use api::{
+    game::{GameResult, Termination},
+    position::Position,
+};
+use chb_chess::{Color, Piece, PieceKind, Square};
+
+pub fn opponent(color: Color) -> Color {
+    match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    }
+}
+
+fn halfmove_clock(fen: &str) -> u32 {
+    fen.split_whitespace()
+        .nth(4)
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(0)
+}
+
+/// True if neither side can possibly deliver checkmate
+pub fn insufficient_material(board: &Position) -> bool {
+    let mut minors = Vec::new();
+    for i in 0u32..64 {
+        let sqr = Square::try_from(i).expect("0-63 are valid squares");
+        match board[sqr] {
+            Piece::Empty | Piece::Filled(PieceKind::King, _) => (),
+            Piece::Filled(kind @ (PieceKind::Bishop | PieceKind::Knight), _) => {
+                minors.push((kind, (sqr.rank() + sqr.file()) % 2))
+            }
+            Piece::Filled(_, _) => return false,
+        }
+    }
+    match minors.as_slice() {
+        [] | [_] => true,
+        [(_, shade), ..] => minors
+            .iter()
+            .all(|(kind, s)| *kind == PieceKind::Bishop && s == shade),
+    }
+}
+
+/// True if `color` has enough material that checkmate is conceivable, i.e. more than a lone
+/// king or a king and a single minor piece. Used to decide games lost on time.
+pub fn has_mating_material(board: &Position, color: Color) -> bool {
+    let mut minors = 0;
+    for i in 0u32..64 {
+        let sqr = Square::try_from(i).expect("0-63 are valid squares");
+        match board[sqr] {
+            Piece::Filled(PieceKind::Bishop | PieceKind::Knight, c) if c == color => minors += 1,
+            Piece::Filled(PieceKind::Pawn | PieceKind::Rook | PieceKind::Queen, c) if c == color => {
+                return true
+            }
+            _ => (),
+        }
+    }
+    minors > 1
+}
+
+/// Checks whether the game has ended in `board`. `repetitions` is the number of times the
+/// current position has occurred, including this one.
+pub fn result(board: &Position, repetitions: usize) -> Option<GameResult> {
+    if board.moves().is_empty() {
+        return Some(if board.in_check() {
+            GameResult::win(opponent(board.color_to_move()), Termination::Checkmate)
+        } else {
+            GameResult::draw(Termination::Stalemate)
+        });
+    }
+    if repetitions >= 3 {
+        return Some(GameResult::draw(Termination::ThreefoldRepetition));
+    }
+    if halfmove_clock(&board.to_fen()) >= 100 {
+        return Some(GameResult::draw(Termination::FiftyMoveRule));
+    }
+    if insufficient_material(board) {
+        return Some(GameResult::draw(Termination::InsufficientMaterial));
+    }
+    None
+}
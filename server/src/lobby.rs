@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use api::{
+    join::SeatGrant,
+    rating::PlayerInfo,
+    seek::{CreateSeek, Seek},
+};
+use tokio::sync::{
+    broadcast::{self, Receiver, Sender},
+    mpsc::UnboundedSender,
+    Mutex,
+};
+
+use crate::session::User;
+
+pub type LobbyState = Arc<Mutex<Lobby>>;
+
+/// Changes to the lobby a socket falls behind by before having to start over
+const BROADCAST_CAPACITY: usize = 64;
+
+/// Open seeks, waiting for someone to accept them
+pub struct Lobby {
+    seeks: Vec<OpenSeek>,
+    broadcast: Sender<LobbyEvent>,
+    next_id: u64,
+    connections: u64,
+}
+
+pub struct OpenSeek {
+    pub seek: Seek,
+    pub user: User,
+    /// The lobby socket that posted the seek. It is removed when the socket closes.
+    pub connection: u64,
+    /// Tells the seeker about the game once the seek is accepted
+    pub matched: UnboundedSender<SeatGrant>,
+}
+
+#[derive(Clone, Debug)]
+pub enum LobbyEvent {
+    Added(Seek),
+    Removed(u64),
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lobby {
+    pub fn new() -> Self {
+        let (broadcast, _) = broadcast::channel(BROADCAST_CAPACITY);
+        Self {
+            seeks: Vec::new(),
+            broadcast,
+            next_id: 0,
+            connections: 0,
+        }
+    }
+
+    /// Identifies a new lobby socket
+    pub fn connect(&mut self) -> u64 {
+        self.connections += 1;
+        self.connections
+    }
+
+    /// The open seeks, along with every change after them
+    pub fn watch(&self) -> (Vec<Seek>, Receiver<LobbyEvent>) {
+        let seeks = self.seeks.iter().map(|s| s.seek.clone()).collect();
+        (seeks, self.broadcast.subscribe())
+    }
+
+    pub fn add(
+        &mut self,
+        options: CreateSeek,
+        player: PlayerInfo,
+        user: User,
+        connection: u64,
+        matched: UnboundedSender<SeatGrant>,
+    ) -> u64 {
+        self.next_id += 1;
+        let seek = Seek {
+            id: self.next_id,
+            player,
+            options,
+        };
+        // No receivers just means nobody is in the lobby
+        _ = self.broadcast.send(LobbyEvent::Added(seek.clone()));
+        self.seeks.push(OpenSeek {
+            seek,
+            user,
+            connection,
+            matched,
+        });
+        self.next_id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&OpenSeek> {
+        self.seeks.iter().find(|s| s.seek.id == id)
+    }
+
+    /// Removes the seek, e.g. once it has been accepted
+    pub fn take(&mut self, id: u64) -> Option<OpenSeek> {
+        let index = self.seeks.iter().position(|s| s.seek.id == id)?;
+        _ = self.broadcast.send(LobbyEvent::Removed(id));
+        Some(self.seeks.remove(index))
+    }
+
+    /// Removes the seek if `connection` posted it
+    pub fn cancel(&mut self, id: u64, connection: u64) {
+        if self.get(id).is_some_and(|s| s.connection == connection) {
+            self.take(id);
+        }
+    }
+
+    /// Removes every seek posted by `connection`
+    pub fn cancel_all(&mut self, connection: u64) {
+        let ids: Vec<_> = self
+            .seeks
+            .iter()
+            .filter(|s| s.connection == connection)
+            .map(|s| s.seek.id)
+            .collect();
+        for id in ids {
+            self.take(id);
+        }
+    }
+}
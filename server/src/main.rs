@@ -1,56 +1,208 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, process, sync::Arc};
 
+use axum::extract::FromRef;
+use axum::middleware;
 use axum::routing::post;
 use axum::{routing::get, Extension, Router};
+use axum_extra::extract::cookie::Key;
 use frontend::{App, AppProps};
-use game::Game;
-use leptos::{get_configuration, log, view};
+use game::{ExecExt, Game, LiveGame};
+use leptos::{get_configuration, view};
 use leptos_axum::{generate_route_list, LeptosRoutes};
+use log::{error, info};
+use simple_logger::SimpleLogger;
 use tokio::sync::{Mutex, RwLock};
 
-use crate::routes::board::{create_board, get_board};
+use crate::config::Settings;
+use crate::lobby::{Lobby, LobbyState};
+use crate::routes::board::{
+    create_board, get_board, get_moves, get_pgn, get_players, import_pgn, list_boards,
+};
+use crate::routes::lobby::join_lobby;
+use crate::routes::user::{get_me, get_user, login, logout, register};
+use crate::session::ensure_session;
+use crate::storage::{GameRecord, Storage};
 use crate::{
     fallback::file_handler,
     routes::board::{join_board, subscribe_to_board},
 };
 
 mod code_gen;
+mod config;
+mod expiry;
 mod fallback;
 mod game;
+mod lobby;
 mod participant;
+mod pgn;
+mod rating;
 mod routes;
 mod session;
+mod shutdown;
+mod storage;
+
+type BoardList = Arc<RwLock<HashMap<String, LiveGame>>>;
+
+#[derive(Clone)]
+struct AppState {
+    boards: BoardList,
+    storage: Storage,
+    lobby: LobbyState,
+    /// Signs session cookies
+    key: Key,
+    settings: Arc<Settings>,
+}
+
+impl FromRef<AppState> for BoardList {
+    fn from_ref(state: &AppState) -> Self {
+        state.boards.clone()
+    }
+}
+
+impl FromRef<AppState> for Storage {
+    fn from_ref(state: &AppState) -> Self {
+        state.storage.clone()
+    }
+}
 
-type BoardList = Arc<RwLock<HashMap<String, Arc<Mutex<Game>>>>>;
+impl FromRef<AppState> for LobbyState {
+    fn from_ref(state: &AppState) -> Self {
+        state.lobby.clone()
+    }
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.key.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Settings> {
+    fn from_ref(state: &AppState) -> Self {
+        state.settings.clone()
+    }
+}
 
 #[tokio::main]
 async fn main() {
+    let settings = Settings::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e:#}");
+        process::exit(2);
+    });
     let conf = get_configuration(None).await.unwrap();
-    let leptos_options = conf.leptos_options;
+    let mut leptos_options = conf.leptos_options;
+    if let Some(addr) = settings.addr {
+        leptos_options.site_addr = addr;
+    }
+    if let Some(root) = &settings.site_root {
+        leptos_options.site_root = root.clone();
+    }
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(|cx| view! {cx, <App/> }).await;
+    let ws_origin = settings.ws_origin.clone();
 
-    simple_logger::init_with_level(log::Level::Debug).expect("couldn't initialize logging");
+    SimpleLogger::new()
+        .with_level(settings.log_level)
+        .init()
+        .expect("couldn't initialize logging");
 
-    let bs_map: BoardList = Arc::new(RwLock::new(HashMap::new()));
-    let state = bs_map;
+    let storage = Storage::open(settings.database_path()).expect("couldn't open database");
+    let bs_map: BoardList = Arc::new(RwLock::new(restore_games(&storage).await));
+    let key = storage.cookie_key().expect("couldn't load cookie key");
+    if let Some(expiry) = settings.idle_expiry {
+        tokio::spawn(expiry::expire_idle_games(bs_map.clone(), expiry));
+    }
+    let state = AppState {
+        boards: bs_map.clone(),
+        storage: storage.clone(),
+        lobby: Arc::new(Mutex::new(Lobby::new())),
+        key: Key::from(&key),
+        settings: Arc::new(settings),
+    };
 
     let api = Router::new()
+        .route("/boards", get(list_boards))
         .route("/board/:id", get(get_board))
         .route("/board/create", post(create_board))
+        .route("/board/import", post(import_pgn))
+        .route("/board/:id/moves", get(get_moves))
+        .route("/board/:id/pgn", get(get_pgn))
+        .route("/board/:id/players", get(get_players))
         .route("/board/:id/subscribe", get(subscribe_to_board))
         .route("/board/join/:id/:play_as", get(join_board))
-        .with_state(state);
+        .route("/lobby", get(join_lobby))
+        .route("/user/me", get(get_me))
+        .route("/user/:name", get(get_user))
+        .route("/user/register", post(register))
+        .route("/user/login", post(login))
+        .route("/user/logout", post(logout))
+        .with_state(state.clone());
 
     let app = Router::new()
         .nest("/api", api)
-        .leptos_routes(leptos_options.clone(), routes, |cx| view! {cx, <App/>})
+        .leptos_routes(leptos_options.clone(), routes, move |cx| {
+            view! {cx, <App ws_origin=ws_origin.clone()/>}
+        })
         .fallback(file_handler)
+        .layer(middleware::from_fn_with_state(state, ensure_session))
         .layer(Extension(Arc::new(leptos_options)));
 
-    log!("Server Listening on {}", addr);
+    info!("Server Listening on {}", addr);
+    // Stops taking new connections, so no games are created while the rest are saved
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown::signal())
         .await
         .unwrap();
+
+    let _suspended = shutdown::suspend_games(&bs_map, &storage).await;
+    tokio::time::sleep(shutdown::NOTICE_PERIOD).await;
+}
+
+/// Loads every unfinished game from storage, along with the clocks and seats of those saved at
+/// shutdown. They wait in setup until players rejoin, who have the usual grace period to do so,
+/// with any computer players started again.
+async fn restore_games(storage: &Storage) -> HashMap<String, LiveGame> {
+    let stored = match storage.unfinished_games() {
+        Ok(games) => games,
+        Err(e) => {
+            error!("Failed to load games: {e}");
+            return HashMap::new();
+        }
+    };
+    let mut snapshots = storage.take_snapshots().unwrap_or_else(|e| {
+        error!("Failed to load snapshots: {e}");
+        HashMap::new()
+    });
+    let mut games = HashMap::new();
+    for game in stored {
+        let id = game.id.clone();
+        match Game::restore(game) {
+            Ok(mut g) => {
+                g.resume_record(GameRecord::new(storage.clone(), id.clone()));
+                let held = match snapshots.remove(&id) {
+                    Some(snapshot) => g.resume_snapshot(snapshot),
+                    None => Vec::new(),
+                };
+                let computer = g.computer().cloned();
+                if let Some(seat) = computer.clone() {
+                    // Started from where the game is now, as it hasn't seen the moves
+                    match participant::computer(&seat.player, g.fen()).await {
+                        Ok(player) => g.seat_computer(seat, player),
+                        Err(e) => error!("Failed to restart computer for game {id}: {e}"),
+                    }
+                }
+                let live = LiveGame::new(g);
+                for (color, connection) in held {
+                    if computer.as_ref().map_or(true, |seat| seat.color != color) {
+                        live.game.clone().hold_seat(color, connection);
+                    }
+                }
+                games.insert(id, live);
+            }
+            Err(e) => error!("Failed to restore game {id}: {e}"),
+        }
+    }
+    info!("Restored {} games", games.len());
+    games
 }
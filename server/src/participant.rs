@@ -1,11 +1,66 @@
-use anyhow::Result;
+use std::{env, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use api::{
+    clock::ClockState,
+    computer::{ComputerPlayer, MAX_BOT_LEVEL},
+    game::GameResult,
+    position::Position,
+    protocol::MoveRejection,
+};
 use axum::async_trait;
 use chb_chess::Move;
+use tokio::sync::Mutex;
+
+use crate::game::Player;
 
+use self::{bot::Bot, uci_engine::UciEngine};
+
+pub mod bot;
+pub mod uci_engine;
 pub mod web_player;
 
 #[async_trait]
 pub trait Participant {
-    async fn get_move(&mut self) -> Result<Move>; // Cannot send error responses, but oh well
+    async fn get_move(&mut self) -> Result<Move>;
+    /// Tells the participant why the last move from `get_move` was not accepted
+    async fn reject_move(&mut self, rejection: MoveRejection) -> Result<()>;
     async fn send_move(&mut self, mv: Move) -> Result<()>;
+    async fn send_clock(&mut self, clock: ClockState) -> Result<()>;
+    async fn send_result(&mut self, result: GameResult) -> Result<()>;
+    /// Ends the participant's connection once someone else has taken its place
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Engine binary used for UCI opponents unless overridden with the `UCI_ENGINE` variable
+const DEFAULT_ENGINE: &str = "stockfish";
+
+/// Starts a computer player for a game starting from `start_fen`
+pub async fn computer(player: &ComputerPlayer, start_fen: String) -> Result<Player> {
+    match player {
+        ComputerPlayer::Uci(settings) => {
+            let path = env::var("UCI_ENGINE").unwrap_or(DEFAULT_ENGINE.to_owned());
+            let engine = UciEngine::spawn(path, settings, start_fen).await?;
+            Ok(Arc::new(Mutex::new(engine)))
+        }
+        ComputerPlayer::Bot { level } => {
+            if !(1..=MAX_BOT_LEVEL).contains(level) {
+                return Err(anyhow!("Bot level must be between 1 and {MAX_BOT_LEVEL}"));
+            }
+            let board = start_fen
+                .parse::<Position>()
+                .map_err(|_| anyhow!("Invalid starting position"))?;
+            Ok(Arc::new(Mutex::new(Bot::new(*level, board))))
+        }
+    }
+}
+
+/// Name recorded for a computer player
+pub fn computer_name(player: &ComputerPlayer) -> String {
+    match player {
+        ComputerPlayer::Uci(_) => "UCI engine".to_owned(),
+        ComputerPlayer::Bot { level } => format!("Bot (level {level})"),
+    }
 }
@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use api::{clock::ClockState, game::GameResult, position::Position, protocol::MoveRejection};
+use axum::async_trait;
+use chb_chess::{Color, Move, Piece, PieceKind, Square};
+use rand::{seq::SliceRandom, thread_rng};
+use tokio::task;
+
+use super::Participant;
+
+const MATE: i32 = 1_000_000;
+
+/// A computer opponent that searches with its own copy of the board
+pub struct Bot {
+    level: u8,
+    board: Position,
+}
+
+impl Bot {
+    /// Level 1 plays random moves, level 2 grabs material, and higher levels search
+    /// `level - 1` plies with alpha-beta
+    pub fn new(level: u8, board: Position) -> Self {
+        Self { level, board }
+    }
+}
+
+#[async_trait]
+impl Participant for Bot {
+    async fn get_move(&mut self) -> Result<Move> {
+        let board = self.board.clone();
+        let level = self.level;
+        task::spawn_blocking(move || match level {
+            0 | 1 => random_move(&board),
+            2 => greedy_move(&board),
+            _ => search(&board, u32::from(level) - 1),
+        })
+        .await?
+        .ok_or(anyhow!("Bot has no legal moves"))
+    }
+
+    async fn reject_move(&mut self, rejection: MoveRejection) -> Result<()> {
+        Err(anyhow!("Bot move was rejected ({rejection})"))
+    }
+
+    async fn send_move(&mut self, mv: Move) -> Result<()> {
+        self.board
+            .make(mv)
+            .map_err(|_| anyhow!("Bot board out of sync"))?;
+        Ok(())
+    }
+
+    async fn send_clock(&mut self, _clock: ClockState) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_result(&mut self, _result: GameResult) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn random_move(board: &Position) -> Option<Move> {
+    board.moves().choose(&mut thread_rng()).copied()
+}
+
+/// Takes the most valuable piece available, moving randomly if nothing can be captured
+fn greedy_move(board: &Position) -> Option<Move> {
+    let mut moves = board.moves();
+    moves.shuffle(&mut thread_rng());
+    moves
+        .into_iter()
+        .max_by_key(|mv| match board[mv.dest] {
+            Piece::Filled(kind, _) => value(kind),
+            Piece::Empty => 0,
+        })
+}
+
+fn search(board: &Position, depth: u32) -> Option<Move> {
+    let mut moves = board.moves();
+    moves.shuffle(&mut thread_rng());
+    order(board, &mut moves);
+    let mut best = None;
+    let mut alpha = -MATE * 2;
+    for mv in moves {
+        let mut child = board.clone();
+        if child.make(mv).is_err() {
+            continue;
+        }
+        let score = -negamax(&child, depth - 1, -MATE * 2, -alpha);
+        if best.is_none() || score > alpha {
+            alpha = score;
+            best = Some(mv);
+        }
+    }
+    best
+}
+
+fn negamax(board: &Position, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+    let mut moves = board.moves();
+    if moves.is_empty() {
+        // Prefer quicker mates by scoring them higher while more depth remains
+        return if board.in_check() {
+            -MATE - depth as i32
+        } else {
+            0
+        };
+    }
+    if depth == 0 {
+        return match board.color_to_move() {
+            Color::White => evaluate(board),
+            Color::Black => -evaluate(board),
+        };
+    }
+    order(board, &mut moves);
+    for mv in moves {
+        let mut child = board.clone();
+        if child.make(mv).is_err() {
+            continue;
+        }
+        let score = -negamax(&child, depth - 1, -beta, -alpha);
+        if score >= beta {
+            return beta;
+        }
+        alpha = alpha.max(score);
+    }
+    alpha
+}
+
+/// Searches captures of valuable pieces first so more branches get cut off
+fn order(board: &Position, moves: &mut [Move]) {
+    moves.sort_by_key(|mv| match board[mv.dest] {
+        Piece::Filled(kind, _) => -value(kind),
+        Piece::Empty => 0,
+    });
+}
+
+fn value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 100,
+        PieceKind::Knight => 320,
+        PieceKind::Bishop => 330,
+        PieceKind::Rook => 500,
+        PieceKind::Queen => 900,
+        PieceKind::King => 0,
+    }
+}
+
+/// Material plus piece-square bonuses, from White's point of view
+fn evaluate(board: &Position) -> i32 {
+    let mut score = 0;
+    for i in 0u32..64 {
+        let sqr = Square::try_from(i).expect("0-63 are valid squares");
+        let Piece::Filled(kind, color) = board[sqr] else {
+            continue;
+        };
+        // Tables are written from White's side with the eighth rank first
+        let (rank, file) = (sqr.rank() as usize, sqr.file() as usize);
+        let row = match color {
+            Color::White => 7 - rank,
+            Color::Black => rank,
+        };
+        let piece_score = value(kind) + table(kind)[row * 8 + file];
+        match color {
+            Color::White => score += piece_score,
+            Color::Black => score -= piece_score,
+        }
+    }
+    score
+}
+
+fn table(kind: PieceKind) -> &'static [i32; 64] {
+    match kind {
+        PieceKind::Pawn => &PAWN_TABLE,
+        PieceKind::Knight => &KNIGHT_TABLE,
+        PieceKind::Bishop => &BISHOP_TABLE,
+        PieceKind::Rook => &ROOK_TABLE,
+        PieceKind::Queen => &QUEEN_TABLE,
+        PieceKind::King => &KING_TABLE,
+    }
+}
+
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
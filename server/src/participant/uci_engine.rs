@@ -0,0 +1,199 @@
+use std::{
+    ffi::OsStr,
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, bail, Result};
+use api::{
+    clock::ClockState,
+    computer::{EngineSettings, SearchLimit},
+    game::GameResult,
+    position::Position,
+    protocol::MoveRejection,
+};
+use axum::async_trait;
+use chb_chess::Move;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+
+use super::Participant;
+
+/// Engine options clients may set. Others, like log files, would let them reach the server.
+const ALLOWED_OPTIONS: [&str; 3] = ["Skill Level", "UCI_Elo", "UCI_LimitStrength"];
+/// Most engines running at once
+const MAX_ENGINES: usize = 4;
+const MAX_DEPTH: u32 = 30;
+/// Milliseconds
+const MAX_MOVE_TIME: u64 = 10_000;
+const MAX_NODES: u64 = 10_000_000;
+
+static RUNNING_ENGINES: AtomicUsize = AtomicUsize::new(0);
+
+/// One of the [`MAX_ENGINES`] engines allowed to run, given back when dropped
+struct EngineSlot;
+
+impl EngineSlot {
+    fn take() -> Result<Self> {
+        RUNNING_ENGINES
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < MAX_ENGINES).then_some(n + 1)
+            })
+            .map_err(|_| anyhow!("Too many engines are running, try again later"))?;
+        Ok(EngineSlot)
+    }
+}
+
+impl Drop for EngineSlot {
+    fn drop(&mut self) {
+        RUNNING_ENGINES.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Plays moves chosen by a UCI engine running as a child process
+pub struct UciEngine {
+    // Held so the engine is killed along with the participant
+    _child: Child,
+    _slot: EngineSlot,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    limit: SearchLimit,
+    start_fen: String,
+    moves: Vec<Move>,
+}
+
+impl UciEngine {
+    /// Starts the engine at `path` and waits until it is ready to search from `start_fen`
+    pub async fn spawn(
+        path: impl AsRef<OsStr>,
+        settings: &EngineSettings,
+        start_fen: String,
+    ) -> Result<Self> {
+        check_options(settings)?;
+        let slot = EngineSlot::take()?;
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(anyhow!("Engine has no stdin"))?;
+        let stdout = child.stdout.take().ok_or(anyhow!("Engine has no stdout"))?;
+        let mut engine = Self {
+            _child: child,
+            _slot: slot,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            limit: clamp(settings.limit),
+            start_fen,
+            moves: Vec::new(),
+        };
+
+        engine.send("uci").await?;
+        engine.wait_for("uciok").await?;
+        for (name, value) in &settings.options {
+            engine
+                .send(&format!("setoption name {name} value {value}"))
+                .await?;
+        }
+        // Castling is then sent and read as the king taking its own rook, as `Position` has it
+        let chess960 = engine
+            .start_fen
+            .parse::<Position>()
+            .is_ok_and(|start| start.is_chess960());
+        if chess960 {
+            engine
+                .send("setoption name UCI_Chess960 value true")
+                .await?;
+        }
+        engine.send("ucinewgame").await?;
+        engine.send("isready").await?;
+        engine.wait_for("readyok").await?;
+        Ok(engine)
+    }
+
+    async fn send(&mut self, command: &str) -> Result<()> {
+        self.stdin.write_all(command.as_bytes()).await?;
+        self.stdin.write_all(b"\n").await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Reads output until a line starting with `prefix`, which is returned
+    async fn wait_for(&mut self, prefix: &str) -> Result<String> {
+        while let Some(line) = self.stdout.next_line().await? {
+            if line.starts_with(prefix) {
+                return Ok(line);
+            }
+        }
+        Err(anyhow!("Engine exited"))
+    }
+}
+
+/// Refuses options that aren't allowed or would break out of the `setoption` command
+fn check_options(settings: &EngineSettings) -> Result<()> {
+    for (name, value) in &settings.options {
+        if !ALLOWED_OPTIONS.contains(&name.as_str()) {
+            bail!("Engine option {name} can't be set");
+        }
+        if value.contains(['\r', '\n']) {
+            bail!("Engine option values must be a single line");
+        }
+    }
+    Ok(())
+}
+
+/// Keeps searches within what the server is willing to spend on them
+fn clamp(limit: SearchLimit) -> SearchLimit {
+    match limit {
+        SearchLimit::Depth(d) => SearchLimit::Depth(d.clamp(1, MAX_DEPTH)),
+        SearchLimit::MoveTime(ms) => SearchLimit::MoveTime(ms.clamp(1, MAX_MOVE_TIME)),
+        SearchLimit::Nodes(n) => SearchLimit::Nodes(n.clamp(1, MAX_NODES)),
+    }
+}
+
+#[async_trait]
+impl Participant for UciEngine {
+    async fn get_move(&mut self) -> Result<Move> {
+        let mut position = format!("position fen {}", self.start_fen);
+        if !self.moves.is_empty() {
+            position.push_str(" moves");
+            for mv in &self.moves {
+                position.push_str(&format!(" {mv}"));
+            }
+        }
+        self.send(&position).await?;
+        let go = match self.limit {
+            SearchLimit::Depth(d) => format!("go depth {d}"),
+            SearchLimit::MoveTime(ms) => format!("go movetime {ms}"),
+            SearchLimit::Nodes(n) => format!("go nodes {n}"),
+        };
+        self.send(&go).await?;
+
+        let line = self.wait_for("bestmove").await?;
+        let best = line
+            .split_whitespace()
+            .nth(1)
+            .ok_or(anyhow!("Engine sent an empty bestmove"))?;
+        best.parse()
+            .map_err(|_| anyhow!("Engine sent an unreadable move: {best}"))
+    }
+
+    async fn reject_move(&mut self, rejection: MoveRejection) -> Result<()> {
+        Err(anyhow!("Engine move was rejected ({rejection})"))
+    }
+
+    async fn send_move(&mut self, mv: Move) -> Result<()> {
+        self.moves.push(mv);
+        Ok(())
+    }
+
+    async fn send_clock(&mut self, _clock: ClockState) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_result(&mut self, _result: GameResult) -> Result<()> {
+        self.send("quit").await
+    }
+}
@@ -1,54 +1,251 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+};
+
 use anyhow::{anyhow, Result};
-use axum::{async_trait, extract::ws::{WebSocket, Message}};
-use chb_chess::Move;
+use api::{
+    clock::ClockState,
+    game::GameResult,
+    position::{position_hash, MoveUpdate, Position, Snapshot},
+    protocol::{ClientMessage, MoveRejection, RejectReason, ServerMessage, WireMessage},
+};
+use axum::{
+    async_trait,
+    extract::ws::{Message, WebSocket},
+};
+use chb_chess::{Color, Move};
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot, Mutex,
+    },
+    task,
+};
+
+use crate::game::{Game, GameEvent};
 
 use super::Participant;
 
+type Writer = Arc<Mutex<SplitSink<WebSocket, Message>>>;
+
+/// The game as the player has been told about it
+struct ToldPosition {
+    board: Position,
+    ply: usize,
+}
+
 pub struct WebPlayer {
-    socket: WebSocket,
+    writer: Writer,
+    moves: mpsc::Receiver<Move>,
+    color: Color,
+    position: Arc<Mutex<ToldPosition>>,
+    /// Whether a move from the socket would be accepted right now
+    expecting_move: Arc<AtomicBool>,
 }
 
 impl WebPlayer {
-    pub fn connect(socket: WebSocket) -> Self {
-        Self { socket }
+    /// Seats the socket as `color` in a game currently at `board` after `ply` moves. Actions
+    /// from the socket go straight to `game`, and `events` from it that aren't sent through
+    /// [`Participant`] are passed on. The receiver fires once the socket closes.
+    pub fn connect(
+        socket: WebSocket,
+        color: Color,
+        board: Position,
+        ply: usize,
+        game: Weak<Mutex<Game>>,
+        events: broadcast::Receiver<GameEvent>,
+    ) -> (Self, oneshot::Receiver<()>) {
+        let (writer, reader) = socket.split();
+        let writer = Arc::new(Mutex::new(writer));
+        let expecting_move = Arc::new(AtomicBool::new(board.color_to_move() == color));
+        let position = Arc::new(Mutex::new(ToldPosition { board, ply }));
+        let (tx, moves) = mpsc::channel(1);
+        let (closed_tx, closed) = oneshot::channel();
+        let reading = read_messages(
+            reader,
+            writer.clone(),
+            position.clone(),
+            expecting_move.clone(),
+            tx,
+            Seat { game, color },
+        );
+        let forwarding = forward_events(
+            events,
+            writer.clone(),
+            position.clone(),
+            expecting_move.clone(),
+            color,
+        );
+        task::spawn(async move {
+            tokio::select! {
+                _ = reading => (),
+                _ = forwarding => (),
+            }
+            _ = closed_tx.send(());
+        });
+        let player = Self {
+            writer,
+            moves,
+            color,
+            position,
+            expecting_move,
+        };
+        (player, closed)
     }
 
-    async fn next(&mut self) -> Result<Message> {
-        let mut msg = self
-            .socket
-            .recv()
-            .await
-            .ok_or(anyhow!("WebSocket for player closed"))?;
-        while msg.is_err() {
-            msg = self
-                .socket
-                .recv()
-                .await
-                .ok_or(anyhow!("WebSocket for player closed"))?;
-        }
+    async fn send(&mut self, msg: ServerMessage) -> Result<()> {
+        send(&self.writer, msg).await
+    }
+}
+
+async fn send(writer: &Writer, msg: ServerMessage) -> Result<()> {
+    writer.lock().await.send(Message::Text(msg.encode())).await?;
+    Ok(())
+}
+
+/// Where the player is sitting
+struct Seat {
+    game: Weak<Mutex<Game>>,
+    color: Color,
+}
+
+/// Forwards moves from the socket while it is the player's turn, rejecting anything else
+async fn read_messages(
+    mut reader: SplitStream<WebSocket>,
+    writer: Writer,
+    position: Arc<Mutex<ToldPosition>>,
+    expecting_move: Arc<AtomicBool>,
+    moves: mpsc::Sender<Move>,
+    seat: Seat,
+) {
+    while let Some(msg) = reader.next().await {
+        let Ok(Message::Text(t)) = msg else {
+            continue;
+        };
+        let reply = match ClientMessage::decode(&t) {
+            Ok(ClientMessage::Move(mv)) if expecting_move.swap(false, Ordering::SeqCst) => {
+                if moves.send(mv).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            Ok(ClientMessage::Move(mv)) => ServerMessage::MoveRejected(MoveRejection {
+                text: mv.to_string(),
+                reason: RejectReason::NotYourTurn,
+            }),
+            Ok(ClientMessage::Resync) => {
+                let position = position.lock().await;
+                ServerMessage::Snapshot(Snapshot {
+                    fen: position.board.to_fen(),
+                    ply: position.ply,
+                })
+            }
+            Ok(ClientMessage::Action(action)) => {
+                let Some(game) = seat.game.upgrade() else {
+                    break;
+                };
+                let acted = game.lock().await.act(seat.color, action).await;
+                match acted {
+                    Ok(()) => continue,
+                    Err(e) => ServerMessage::Error(e.to_string()),
+                }
+            }
+            Ok(ClientMessage::Hello { .. }) => continue,
+            Err(_) => ServerMessage::MoveRejected(MoveRejection {
+                text: t,
+                reason: RejectReason::ParseError,
+            }),
+        };
+        _ = send(&writer, reply).await;
+    }
+}
 
-        Ok(msg.expect("must be ok"))
+/// Passes on offers, takebacks and shutdowns, which players aren't told about as participants
+async fn forward_events(
+    mut events: broadcast::Receiver<GameEvent>,
+    writer: Writer,
+    position: Arc<Mutex<ToldPosition>>,
+    expecting_move: Arc<AtomicBool>,
+    color: Color,
+) {
+    loop {
+        let msg = match events.recv().await {
+            Ok(GameEvent::Offers(offers)) => ServerMessage::Offers(offers),
+            Ok(GameEvent::TakeBack(snapshot)) => {
+                let mut position = position.lock().await;
+                let Ok(board) = snapshot.fen.parse::<Position>() else {
+                    continue;
+                };
+                expecting_move.store(board.color_to_move() == color, Ordering::SeqCst);
+                position.board = board;
+                position.ply = snapshot.ply;
+                ServerMessage::Snapshot(snapshot)
+            }
+            Ok(GameEvent::Shutdown) => {
+                _ = send(&writer, ServerMessage::Restarting).await;
+                break;
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        if send(&writer, msg).await.is_err() {
+            break;
+        }
     }
 }
 
 #[async_trait]
 impl Participant for WebPlayer {
     async fn get_move(&mut self) -> Result<Move> {
-        match self.next().await? {
-            Message::Text(t) => {
-                match t.split_once(':') {
-                    Some(("move", m)) if m.trim().parse::<Move>().is_ok() => {
-                        Ok(m.parse().expect("Validated"))
-                    },
-                    _ => self.get_move().await,
-                }
-            },
-            _ => self.get_move().await,
-        }
+        self.moves
+            .recv()
+            .await
+            .ok_or(anyhow!("WebSocket for player closed"))
+    }
+
+    async fn reject_move(&mut self, rejection: MoveRejection) -> Result<()> {
+        self.expecting_move.store(true, Ordering::SeqCst);
+        self.send(ServerMessage::MoveRejected(rejection)).await
     }
 
     async fn send_move(&mut self, mv: Move) -> Result<()> {
-        self.socket.send(Message::Text(format!("move: {mv}"))).await?;
+        let update = {
+            let mut position = self.position.lock().await;
+            position
+                .board
+                .make(mv)
+                .map_err(|_| anyhow!("Player board out of sync"))?;
+            position.ply += 1;
+            self.expecting_move.store(
+                position.board.color_to_move() == self.color,
+                Ordering::SeqCst,
+            );
+            MoveUpdate {
+                mv,
+                ply: position.ply,
+                hash: position_hash(&position.board),
+            }
+        };
+        self.send(ServerMessage::Move(update)).await
+    }
+
+    async fn send_clock(&mut self, clock: ClockState) -> Result<()> {
+        self.send(ServerMessage::Clock(clock)).await
+    }
+
+    async fn send_result(&mut self, result: GameResult) -> Result<()> {
+        self.expecting_move.store(false, Ordering::SeqCst);
+        self.send(ServerMessage::Result(result)).await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.expecting_move.store(false, Ordering::SeqCst);
+        self.writer.lock().await.close().await?;
         Ok(())
     }
 }
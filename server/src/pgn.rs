@@ -0,0 +1,355 @@
+use anyhow::{anyhow, bail, Result};
+use api::{
+    clock::{Bonus, ClockState},
+    position::Position,
+};
+use chb_chess::{Color, Move, Piece, PieceKind};
+
+use crate::game::Game;
+
+/// A game read from PGN
+pub struct ParsedPgn {
+    pub start: Position,
+    pub moves: Vec<Move>,
+}
+
+/// Writes `game` as PGN, with the Seven Tag Roster and clock comments
+pub fn write(game: &Game) -> Result<String> {
+    let names = game.names();
+    let name = |color: Color| names[color].clone().unwrap_or("?".to_owned());
+    let result = game.result().map_or("*", |r| r.score());
+    let mut board = game
+        .start_fen()
+        .parse::<Position>()
+        .map_err(|_| anyhow!("Invalid starting position"))?;
+
+    let mut tags = vec![
+        ("Event", "Casual game".to_owned()),
+        ("Site", "web_chess".to_owned()),
+        ("Date", date(game.created_at())),
+        ("Round", "-".to_owned()),
+        ("White", name(Color::White)),
+        ("Black", name(Color::Black)),
+        ("Result", result.to_owned()),
+    ];
+    if game.start_fen() != Position::default().to_fen() {
+        if board.is_chess960() {
+            tags.push(("Variant", "Chess960".to_owned()));
+        }
+        tags.push(("SetUp", "1".to_owned()));
+        tags.push(("FEN", game.start_fen().to_owned()));
+    }
+    if let Some(tc) = game.time_control() {
+        let base = tc.base / 1000;
+        let value = match tc.bonus {
+            Bonus::None => format!("{base}+0"),
+            Bonus::Increment(ms) => format!("{base}+{}", ms / 1000),
+            // PGN only has a form for increments, so delays are left out rather than passed
+            // off as one
+            Bonus::Bronstein(_) | Bonus::Delay(_) => base.to_string(),
+        };
+        tags.push(("TimeControl", value));
+    }
+    let mut pgn = String::new();
+    for (key, value) in tags {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        pgn.push_str(&format!("[{key} \"{value}\"]\n"));
+    }
+    pgn.push('\n');
+
+    let mut number = fullmove_number(game.start_fen());
+    let mut tokens = Vec::new();
+    for (i, played) in game.history().iter().enumerate() {
+        match board.color_to_move() {
+            Color::White => tokens.push(format!("{number}.")),
+            Color::Black if i == 0 => tokens.push(format!("{number}...")),
+            Color::Black => (),
+        }
+        tokens.push(san(&board, played.mv));
+        if let Some(clock) = played.clock {
+            tokens.push(format!("{{[%clk {}]}}", clock_time(clock, board.color_to_move())));
+        }
+        if board.color_to_move() == Color::Black {
+            number += 1;
+        }
+        board
+            .make(played.mv)
+            .map_err(|_| anyhow!("Illegal move in game history"))?;
+    }
+    tokens.push(result.to_owned());
+
+    // Export format keeps lines under 80 characters
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() + 1 > 79 {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    Ok(pgn)
+}
+
+/// Reads the first game in `text`. Comments, variations and annotations are skipped.
+pub fn read(text: &str) -> Result<ParsedPgn> {
+    let mut fen = None;
+    let mut movetext = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(tag) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if let Some((key, value)) = tag.split_once(char::is_whitespace) {
+                if key == "FEN" {
+                    fen = Some(value.trim().trim_matches('"').to_owned());
+                }
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    let mut board = match fen {
+        Some(f) => f
+            .parse::<Position>()
+            .map_err(|_| anyhow!("Invalid FEN tag: {f}"))?,
+        None => Position::default(),
+    };
+    let start = board.clone();
+    let mut moves = Vec::new();
+    for token in movetext_tokens(&movetext) {
+        if matches!(token.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+            break;
+        }
+        let mv = parse_san(&board, &token)?;
+        board
+            .make(mv)
+            .map_err(|_| anyhow!("Illegal move {token}"))?;
+        moves.push(mv);
+    }
+    Ok(ParsedPgn { start, moves })
+}
+
+/// Splits movetext into SAN moves and results, dropping everything else
+fn movetext_tokens(movetext: &str) -> Vec<String> {
+    let mut cleaned = String::new();
+    let (mut comment, mut line_comment, mut variation) = (false, false, 0u32);
+    for c in movetext.chars() {
+        match c {
+            '\n' if line_comment => line_comment = false,
+            _ if line_comment => (),
+            '}' if comment => comment = false,
+            _ if comment => (),
+            '{' => comment = true,
+            ';' => line_comment = true,
+            '(' => variation += 1,
+            ')' => variation = variation.saturating_sub(1),
+            _ if variation > 0 => (),
+            c => cleaned.push(c),
+        }
+        if matches!(c, '{' | '}' | '(' | ')' | ';' | '\n') {
+            cleaned.push(' ');
+        }
+    }
+    cleaned
+        .split_whitespace()
+        .filter_map(|t| {
+            if matches!(t, "1-0" | "0-1" | "1/2-1/2" | "*") {
+                return Some(t.to_owned());
+            }
+            // Move numbers may be attached to the move, as in `1.e4`
+            let t = t.rsplit('.').next().unwrap_or(t);
+            let t = t.trim_end_matches(['!', '?', '+', '#']);
+            (!t.is_empty() && !t.starts_with('$')).then(|| t.to_owned())
+        })
+        .collect()
+}
+
+/// Finds the legal move in `board` written as `token`
+fn parse_san(board: &Position, token: &str) -> Result<Move> {
+    // Some writers use zeros for castling
+    let token = token.replace('0', "O");
+    let found = board
+        .moves()
+        .into_iter()
+        .filter(|mv| san_body(board, *mv) == token)
+        .collect::<Vec<_>>();
+    match found.as_slice() {
+        [mv] => Ok(*mv),
+        [] => bail!("No legal move matches {token}"),
+        _ => bail!("Ambiguous move {token}"),
+    }
+}
+
+/// Standard algebraic notation for `mv`, which must be legal in `board`
+pub fn san(board: &Position, mv: Move) -> String {
+    let mut san = san_body(board, mv);
+    let mut after = board.clone();
+    if after.make(mv).is_ok() && after.in_check() {
+        san.push(if after.moves().is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+/// SAN without the check or mate suffix
+fn san_body(board: &Position, mv: Move) -> String {
+    let Piece::Filled(kind, _) = board[mv.origin] else {
+        return mv.to_string();
+    };
+    let (origin, dest) = (mv.origin.to_string(), mv.dest.to_string());
+    if kind == PieceKind::King && is_castling(board, mv) {
+        return if mv.dest.file() > mv.origin.file() {
+            "O-O".to_owned()
+        } else {
+            "O-O-O".to_owned()
+        };
+    }
+
+    let capture = !matches!(board[mv.dest], Piece::Empty)
+        || (kind == PieceKind::Pawn && mv.origin.file() != mv.dest.file());
+    let mut san = String::new();
+    if kind == PieceKind::Pawn {
+        if capture {
+            san.push_str(&origin[..1]);
+        }
+    } else {
+        san.push_str(&kind.to_string().to_uppercase());
+        let rivals = board
+            .moves()
+            .into_iter()
+            .filter(|other| {
+                other.dest == mv.dest
+                    && other.origin != mv.origin
+                    && matches!(board[other.origin], Piece::Filled(k, _) if k == kind)
+            })
+            .collect::<Vec<_>>();
+        if !rivals.is_empty() {
+            let shares_file = rivals.iter().any(|r| r.origin.file() == mv.origin.file());
+            let shares_rank = rivals.iter().any(|r| r.origin.rank() == mv.origin.rank());
+            match (shares_file, shares_rank) {
+                (false, _) => san.push_str(&origin[..1]),
+                (true, false) => san.push_str(&origin[1..]),
+                (true, true) => san.push_str(&origin),
+            }
+        }
+    }
+    if capture {
+        san.push('x');
+    }
+    san.push_str(&dest);
+    if let Piece::Filled(promotion, _) = mv.promotion {
+        san.push('=');
+        san.push_str(&promotion.to_string().to_uppercase());
+    }
+    san
+}
+
+/// Whether the king move `mv` castles, either by taking its own rook or by going from the e-file
+/// to the g- or c-file along its rank
+fn is_castling(board: &Position, mv: Move) -> bool {
+    let Piece::Filled(PieceKind::King, color) = board[mv.origin] else {
+        return false;
+    };
+    let takes_own_rook = matches!(board[mv.dest], Piece::Filled(PieceKind::Rook, c) if c == color);
+    let two_from_e = mv.origin.rank() == mv.dest.rank()
+        && mv.origin.file() == 4
+        && matches!(mv.dest.file(), 2 | 6);
+    takes_own_rook || two_from_e
+}
+
+fn fullmove_number(fen: &str) -> u32 {
+    fen.split_whitespace()
+        .nth(5)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1)
+}
+
+/// `h:mm:ss` left on `color`'s clock
+fn clock_time(clock: ClockState, color: Color) -> String {
+    let secs = clock.remaining(color).as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// PGN date (`YYYY.MM.DD`) of a Unix timestamp
+fn date(timestamp: i64) -> String {
+    // Days to civil date, from Howard Hinnant's `chrono`-compatible algorithms
+    let days = timestamp.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `uci` is written as `expected` in `fen`, and read back as the same move
+    fn round_trip(fen: &str, uci: &str, expected: &str) {
+        let board = fen.parse::<Position>().unwrap_or_else(|_| panic!("invalid FEN {fen}"));
+        let mv = uci.parse::<Move>().unwrap_or_else(|_| panic!("invalid move {uci}"));
+        assert_eq!(san(&board, mv), expected);
+        let parsed = parse_san(&board, expected).unwrap();
+        assert_eq!(parsed.to_string(), mv.to_string());
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        round_trip(fen, "e1g1", "O-O");
+        round_trip(fen, "e1c1", "O-O-O");
+        let black = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+        round_trip(black, "e8g8", "O-O");
+        round_trip(black, "e8c8", "O-O-O");
+    }
+
+    #[test]
+    fn castling_with_zeros() {
+        let board = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"
+            .parse::<Position>()
+            .unwrap();
+        assert_eq!(parse_san(&board, "0-0-0").unwrap().to_string(), "e1c1");
+    }
+
+    #[test]
+    fn chess960_castling() {
+        let fen = "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1";
+        round_trip(fen, "g1h1", "O-O");
+        round_trip(fen, "g1b1", "O-O-O");
+    }
+
+    #[test]
+    fn king_steps_are_not_castling() {
+        round_trip("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "e1f1", "Kf1");
+    }
+
+    #[test]
+    fn promotion() {
+        let fen = "8/P7/8/8/8/4k3/8/7K w - - 0 1";
+        round_trip(fen, "a7a8q", "a8=Q");
+        round_trip(fen, "a7a8n", "a8=N");
+    }
+
+    #[test]
+    fn promotion_with_capture() {
+        round_trip("1r6/P7/8/8/8/4k3/8/7K w - - 0 1", "a7b8r", "axb8=R");
+    }
+
+    #[test]
+    fn disambiguation() {
+        // By file, by rank, and by both when neither is enough
+        round_trip("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "b1d2", "Nbd2");
+        round_trip("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3");
+        round_trip("4k3/8/8/8/8/Q1Q5/8/Q3K3 w - - 0 1", "a3b2", "Qa3b2");
+    }
+}
@@ -0,0 +1,169 @@
+//! Glicko-2, as described in <http://www.glicko.net/glicko/glicko2.pdf>. Every rated game is
+//! treated as its own rating period.
+
+use std::f64::consts::PI;
+
+use api::rating::{Category, Rating};
+
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Constrains how quickly volatility changes
+const TAU: f64 = 0.5;
+/// Converts between the Glicko and Glicko-2 scales
+const SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000001;
+/// Ratings with a deviation above this are provisional
+const PROVISIONAL_DEVIATION: f64 = 110.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Glicko {
+    /// The rating after one game against `opponent`, where `score` is 1 for a win, 0.5 for a
+    /// draw and 0 for a loss
+    pub fn update(self, opponent: Glicko, score: f64) -> Glicko {
+        self.update_period(&[(opponent, score)])
+    }
+
+    /// The rating after a rating period with `games`, each an opponent and a score
+    fn update_period(self, games: &[(Glicko, f64)]) -> Glicko {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+
+        // Sums over the games of g^2 E (1 - E) and g (s - E)
+        let (mut information, mut improvement) = (0.0, 0.0);
+        for (opponent, score) in games {
+            let mu_j = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let phi_j = opponent.deviation / SCALE;
+            let g = 1.0 / (1.0 + 3.0 * phi_j.powi(2) / PI.powi(2)).sqrt();
+            let expected = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+            information += g.powi(2) * expected * (1.0 - expected);
+            improvement += g * (score - expected);
+        }
+        let v = 1.0 / information;
+        let delta = v * improvement;
+
+        let volatility = self.new_volatility(phi, v, delta);
+        let phi_star = (phi.powi(2) + volatility.powi(2)).sqrt();
+        let new_phi = 1.0 / (1.0 / phi_star.powi(2) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi.powi(2) * improvement;
+
+        Glicko {
+            rating: SCALE * new_mu + DEFAULT_RATING,
+            deviation: (SCALE * new_phi).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+
+    /// Step 5 of the paper, finding the new volatility with the Illinois algorithm
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = self.volatility.powi(2).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta.powi(2) - phi.powi(2) - v - ex) / (2.0 * (phi.powi(2) + v + ex).powi(2))
+                - (x - a) / TAU.powi(2)
+        };
+
+        let mut lower = a;
+        let mut upper = if delta.powi(2) > phi.powi(2) + v {
+            (delta.powi(2) - phi.powi(2) - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > CONVERGENCE {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+            if f_c * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = c;
+            f_upper = f_c;
+        }
+        (lower / 2.0).exp()
+    }
+
+    pub fn is_provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    pub fn info(&self, category: Category, games: u32) -> Rating {
+        Rating {
+            category,
+            rating: self.rating,
+            deviation: self.deviation,
+            games,
+            provisional: self.is_provisional(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glicko(rating: f64, deviation: f64) -> Glicko {
+        Glicko {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    #[test]
+    fn glickman_example() {
+        // The worked example from the paper, which rounds to two decimal places
+        let player = glicko(1500.0, 200.0);
+        let games = [
+            (glicko(1400.0, 30.0), 1.0),
+            (glicko(1550.0, 100.0), 0.0),
+            (glicko(1700.0, 300.0), 0.0),
+        ];
+        let updated = player.update_period(&games);
+        assert!((updated.rating - 1464.06).abs() < 0.05, "{updated:?}");
+        assert!((updated.deviation - 151.52).abs() < 0.05, "{updated:?}");
+        assert!((updated.volatility - 0.05999).abs() < 0.00001, "{updated:?}");
+    }
+
+    #[test]
+    fn single_game_matches_period() {
+        let player = glicko(1600.0, 120.0);
+        let opponent = glicko(1450.0, 80.0);
+        assert_eq!(
+            player.update(opponent, 0.5),
+            player.update_period(&[(opponent, 0.5)])
+        );
+    }
+
+    #[test]
+    fn winning_raises_rating() {
+        let won = Glicko::default().update(Glicko::default(), 1.0);
+        let lost = Glicko::default().update(Glicko::default(), 0.0);
+        assert!(won.rating > DEFAULT_RATING);
+        assert!(lost.rating < DEFAULT_RATING);
+        assert!(won.deviation < DEFAULT_DEVIATION);
+    }
+}
@@ -1 +1,3 @@
 pub mod board;
+pub mod lobby;
+pub mod user;
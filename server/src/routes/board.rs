@@ -1,61 +1,308 @@
-use api::join::JoinBoard;
+use anyhow::{anyhow, bail, Result};
+use api::{
+    create::{CreateBoard, ImportPgn},
+    game::{GameInfo, History, Offers},
+    join::{JoinBoard, JoinQuery, SeatGrant},
+    listing::{BoardPage, BoardSummary, GameStatus, ListBoards, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    position::Position,
+    protocol::{ClientMessage, ServerMessage, WireMessage, PROTOCOL_VERSION},
+    rating::{Category, PlayerInfo},
+    start::{StartPosition, CHESS960_POSITIONS},
+};
 use std::sync::Arc;
 
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
     },
-    http::StatusCode,
+    http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
-use chb_chess::{Board, BoardBuilder, Color};
-use futures::{SinkExt, StreamExt};
-use leptos::log;
-use tokio::sync::Mutex;
+use chb_chess::Color;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use log::{info, warn};
+use rand::{thread_rng, Rng};
+use tokio::sync::{broadcast::error::RecvError, Mutex};
 
 use crate::{
     code_gen::get_code,
-    game::{ExecExt, Game},
-    participant::web_player::WebPlayer,
+    config::Settings,
+    game::{rules, ExecExt, Game, GameEvent, LiveGame},
+    participant::{self, web_player::WebPlayer},
+    pgn,
+    session::User,
+    storage::{GameRecord, Storage},
     BoardList,
 };
 
 pub async fn get_board(
     State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
     Path(id): Path<String>,
-) -> Result<Json<Board>, StatusCode> {
-    Ok(Json(
-        locked_board_list
-            .read()
-            .await
-            .get(&id)
-            .ok_or(StatusCode::NOT_FOUND)?
-            .lock()
-            .await
-            .board()
-            .clone(),
-    ))
+) -> Result<Json<GameInfo>, StatusCode> {
+    with_game(&locked_board_list, &storage, &id, |game| {
+        Ok(Json(GameInfo {
+            board: game.board().clone(),
+            result: game.result(),
+            clock: game.clock(),
+        }))
+    })
+    .await
+}
+
+pub async fn get_moves(
+    State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
+    Path(id): Path<String>,
+) -> Result<Json<History>, StatusCode> {
+    with_game(&locked_board_list, &storage, &id, |game| {
+        game.history_info()
+            .map(Json)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    })
+    .await
+}
+
+pub async fn get_pgn(
+    State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let pgn = with_game(&locked_board_list, &storage, &id, |game| {
+        pgn::write(game).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    })
+    .await?;
+    let headers = [
+        (header::CONTENT_TYPE, "application/x-chess-pgn".to_owned()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{id}.pgn\""),
+        ),
+    ];
+    Ok((headers, pgn))
+}
+
+/// Live boards matching the filters, newest first. Games finished before the last restart
+/// are only kept in storage and aren't listed.
+pub async fn list_boards(
+    State(locked_board_list): State<BoardList>,
+    Query(query): Query<ListBoards>,
+) -> Json<BoardPage> {
+    let board_list = locked_board_list.read().await;
+    let mut boards = Vec::new();
+    // Summaries are read instead of the games, so busy games don't hold up the list
+    for (id, live) in board_list.iter() {
+        let game = live.summary.borrow().clone();
+        let summary = BoardSummary {
+            id: id.clone(),
+            status: game.status,
+            names: game.names,
+            time_control: game.time_control,
+            rated: game.rated,
+            fen: game.fen,
+            result: game.result,
+            created_at: game.created_at,
+        };
+        let matches = query.status.map_or(true, |s| s == summary.status)
+            && query
+                .category
+                .map_or(true, |c| c == Category::of(summary.time_control))
+            && query.rated.map_or(true, |r| r == summary.rated);
+        if matches {
+            boards.push(summary);
+        }
+    }
+    drop(board_list);
+    boards.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let total = boards.len();
+    let boards = boards
+        .into_iter()
+        .skip(query.page.saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    Json(BoardPage {
+        boards,
+        total,
+        page: query.page,
+        per_page,
+    })
+}
+
+/// Names and ratings of whoever is seated, white first
+pub async fn get_players(
+    State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
+    Path(id): Path<String>,
+) -> Result<Json<[Option<PlayerInfo>; 2]>, StatusCode> {
+    with_game(&locked_board_list, &storage, &id, |game| {
+        let category = Category::of(game.time_control());
+        let player = |name: &Option<String>| -> Result<_, StatusCode> {
+            let Some(name) = name else {
+                return Ok(None);
+            };
+            let rating = storage
+                .user_rating(name, category)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            Ok(Some(PlayerInfo {
+                name: name.clone(),
+                rating,
+            }))
+        };
+        let [white, black] = game.names();
+        Ok(Json([player(white)?, player(black)?]))
+    })
+    .await
+}
+
+/// Creates a new board from the moves of an uploaded PGN, optionally stopping after `ply`
+/// half moves
+pub async fn import_pgn(
+    State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
+    State(settings): State<Arc<Settings>>,
+    Query(ImportPgn { ply }): Query<ImportPgn>,
+    body: String,
+) -> Result<String, StatusCode> {
+    let parsed = pgn::read(&body).map_err(|e| {
+        warn!("Failed to read PGN: {e}");
+        StatusCode::BAD_REQUEST
+    })?;
+    let mut game = Game::new(parsed.start, None);
+    let ply = ply.unwrap_or(parsed.moves.len());
+    for mv in parsed.moves.into_iter().take(ply) {
+        game.replay(mv, None)
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+    }
+    let (id, _) = insert_game(&locked_board_list, storage, &settings, game).await?;
+    Ok(id)
 }
 
 pub async fn create_board(
     State(locked_board_list): State<BoardList>,
-    Json(builder): Json<Option<BoardBuilder>>,
+    State(storage): State<Storage>,
+    State(settings): State<Arc<Settings>>,
+    Json(create): Json<CreateBoard>,
 ) -> Result<String, StatusCode> {
-    let board = if let Some(bb) = builder {
-        bb.build().map_err(|_| StatusCode::BAD_REQUEST)?
-    } else {
-        Board::default()
+    let game = new_game(create).await?;
+    let (id, _) = insert_game(&locked_board_list, storage, &settings, game).await?;
+    Ok(id)
+}
+
+/// Sets up a game as requested, seating the computer if there is one
+pub async fn new_game(
+    CreateBoard {
+        builder,
+        start,
+        time_control,
+        computer,
+        rated,
+    }: CreateBoard,
+) -> Result<Game, StatusCode> {
+    if time_control.is_some_and(|tc| tc.base == 0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Computers don't have ratings
+    if rated && computer.is_some() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Only even starts count towards ratings, which custom boards may not be
+    if rated && (builder.is_some() || !start.as_ref().map_or(true, StartPosition::is_balanced)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let board = match (builder, start) {
+        (Some(_), Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        (Some(bb), None) => bb.build().map_err(|_| StatusCode::BAD_REQUEST)?.into(),
+        (None, Some(start)) => start
+            .fen(|| thread_rng().gen_range(0..CHESS960_POSITIONS))
+            .ok_or(StatusCode::BAD_REQUEST)?
+            .parse::<Position>()
+            .map_err(|_| StatusCode::BAD_REQUEST)?,
+        (None, None) => Position::default(),
     };
-    let mut board_list = locked_board_list.write().await;
-    let mut id = get_code();
-    // Probably not necessary, but might as well
-    while board_list.contains_key(&id) {
-        id = get_code();
+    let mut game = Game::new(board, time_control);
+    game.set_rated(rated);
+    if let Some(seat) = computer {
+        let player = participant::computer(&seat.player, game.fen())
+            .await
+            .map_err(|e| {
+                warn!("Failed to start computer player: {e}");
+                StatusCode::BAD_REQUEST
+            })?;
+        game.set_name(seat.color, participant::computer_name(&seat.player));
+        game.seat_computer(seat, player);
+    }
+    Ok(game)
+}
+
+/// Picks an unused code for `game`, saving it and adding it to the list. Fails with 503 if
+/// there are already as many unfinished games as allowed.
+pub async fn insert_game(
+    locked_board_list: &BoardList,
+    storage: Storage,
+    settings: &Settings,
+    mut game: Game,
+) -> Result<(String, Arc<Mutex<Game>>), StatusCode> {
+    // The database is checked before locking the list, which isn't held while it is read
+    let (id, mut board_list) = loop {
+        let id = get_code(settings.code_length);
+        if storage.has_game_blocking(id.clone()).await.unwrap_or(false) {
+            continue;
+        }
+        let board_list = locked_board_list.write().await;
+        // Probably not necessary, but might as well
+        if !board_list.contains_key(&id) {
+            break (id, board_list);
+        }
+    };
+    if let Some(max) = settings.max_games {
+        // Counted from the summaries, so no game is waited on while the list is locked
+        let unfinished = board_list
+            .values()
+            .filter(|live| live.summary.borrow().status != GameStatus::Finished)
+            .count();
+        if unfinished >= max {
+            warn!("Refusing new game, {unfinished} are already being played");
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+    game.persist(GameRecord::new(storage, id.clone()));
+    let live = LiveGame::new(game);
+    let game = live.game.clone();
+    board_list.insert(id.clone(), live);
+    Ok((id, game))
+}
+
+/// Runs `f` on the game with `id`, whether it is live or only in storage
+async fn with_game<T>(
+    locked_board_list: &BoardList,
+    storage: &Storage,
+    id: &str,
+    f: impl FnOnce(&Game) -> Result<T, StatusCode>,
+) -> Result<T, StatusCode> {
+    let game = locked_board_list
+        .read()
+        .await
+        .get(id)
+        .map(|live| live.game.clone());
+    match game {
+        Some(game) => f(&*game.lock().await),
+        // Finished games from before the last restart are only in storage
+        None => {
+            let stored = storage
+                .load_game(id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            let game = Game::restore(stored).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            f(&game)
+        }
     }
-    board_list.insert(id.clone(), Arc::new(Mutex::new(Game::new(board))));
-    Ok(id)
 }
 
 pub async fn subscribe_to_board(
@@ -65,32 +312,86 @@ pub async fn subscribe_to_board(
 ) -> impl IntoResponse {
     let board_list = locked_board_list.read().await;
     let board_state = match board_list.get(&id) {
-        Some(bs) => bs.clone(),
+        Some(live) => live.game.clone(),
         None => return Err(StatusCode::NOT_FOUND),
     };
 
-    Ok(wsu.on_upgrade(|ws: WebSocket| async move {
-        sync_board(ws, board_state).await;
+    Ok(wsu.on_upgrade(|mut ws: WebSocket| async move {
+        match handshake(&mut ws).await {
+            Ok(_) => sync_board(ws, board_state).await,
+            Err(e) => warn!("Spectator handshake failed: {e}"),
+        }
     }))
 }
 
 pub async fn join_board(
     wsu: WebSocketUpgrade,
     State(locked_board_list): State<BoardList>,
-    Path(id): Path<String>,
-    Path(play_as): Path<Color>,
-) -> impl IntoResponse {
-    // Should really check if the player of that color is already set.
-    log!("Joining board {id} as {play_as}");
+    Path((id, play_as)): Path<(String, String)>,
+    Query(JoinQuery { token }): Query<JoinQuery>,
+    user: User,
+) -> Result<impl IntoResponse, StatusCode> {
+    let play_as = play_as
+        .parse::<Color>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    info!("{} joining board {id} as {play_as}", user.name());
     let game = match locked_board_list.read().await.get(&id) {
-        Some(g) => g.clone(),
+        Some(live) => live.game.clone(),
         None => return Err(StatusCode::NOT_FOUND),
     };
+    {
+        let g = game.lock().await;
+        if !g.can_claim(play_as, token.as_deref()) {
+            return Err(StatusCode::CONFLICT);
+        }
+        if g.is_rated() {
+            // Rated games are between two different registered users
+            let opponent = &g.names()[rules::opponent(play_as)];
+            if !user.is_registered() || opponent.as_ref() == Some(&user.name()) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+    }
 
     Ok(wsu.on_upgrade(move |mut ws: WebSocket| async move {
+        if let Err(e) = handshake(&mut ws).await {
+            warn!("Player handshake failed: {e}");
+            return;
+        }
         let mut g = game.lock().await;
-        _ = ws.send(Message::Text(format!("fen: {}", g.board()))).await;
-        g.set_player(play_as, Some(Arc::new(Mutex::new(WebPlayer::connect(ws)))));
+        // Someone else may have taken the seat since the upgrade was accepted
+        let seat = match g.claim_seat(play_as, token.as_deref()) {
+            Ok(seat) => seat,
+            Err(e) => {
+                let msg = ServerMessage::Error(e.to_string());
+                _ = ws.send(Message::Text(msg.encode())).await;
+                return;
+            }
+        };
+        let grant = SeatGrant {
+            id,
+            color: play_as,
+            token: seat.token,
+        };
+        let messages = [
+            ServerMessage::Seat(grant),
+            ServerMessage::Snapshot(g.snapshot()),
+            ServerMessage::Offers(g.offers()),
+        ];
+        for msg in messages {
+            _ = ws.send(Message::Text(msg.encode())).await;
+        }
+        let (player, closed) = WebPlayer::connect(
+            ws,
+            play_as,
+            g.board().clone(),
+            g.history().len(),
+            Arc::downgrade(&game),
+            g.watch(),
+        );
+        g.set_name(play_as, user.name());
+        g.set_player(play_as, Some(Arc::new(Mutex::new(player))));
+        game.clone().watch_seat(play_as, seat.connection, closed);
 
         if g.is_active() {
             drop(g);
@@ -100,18 +401,106 @@ pub async fn join_board(
 }
 
 async fn sync_board(stream: WebSocket, locked_game: Arc<Mutex<Game>>) {
-    let game = locked_game.lock().await;
-    // Send fen to update local board
-    let (mut writer, _) = stream.split();
-    let _ = writer
-        .send(Message::Text(format!("fen:{}", game.fen())))
-        .await;
-    let mut rx = game.watch();
-
-    while let Ok(m) = rx.recv().await {
-        match writer.send(Message::Text(format!("move: {m}"))).await {
-            Ok(_) => (),
-            Err(e) => log!("Failed to send message to websocket: {e}"),
+    let (mut writer, mut reader) = stream.split();
+    let mut rx = {
+        let game = locked_game.lock().await;
+        if let Err(e) = send_snapshot(&mut writer, &game).await {
+            warn!("Failed to sync spectator: {e}");
+            return;
+        }
+        if game.is_finished() {
+            return;
+        }
+        game.watch()
+    };
+
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            msg = reader.next() => match msg {
+                Some(Ok(Message::Text(t))) => {
+                    if let Ok(ClientMessage::Resync) = ClientMessage::decode(&t) {
+                        let snapshot = locked_game.lock().await.snapshot();
+                        let msg = ServerMessage::Snapshot(snapshot);
+                        if writer.send(Message::Text(msg.encode())).await.is_err() {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                Some(_) => continue,
+                None => break,
+            },
+        };
+        let msg = match event {
+            Ok(GameEvent::Move(m)) => ServerMessage::Move(m),
+            Ok(GameEvent::Clock(c)) => ServerMessage::Clock(c),
+            Ok(GameEvent::End(result)) => ServerMessage::Result(result),
+            Ok(GameEvent::Offers(offers)) => ServerMessage::Offers(offers),
+            Ok(GameEvent::TakeBack(snapshot)) => ServerMessage::Snapshot(snapshot),
+            Ok(GameEvent::Shutdown) => {
+                _ = writer
+                    .send(Message::Text(ServerMessage::Restarting.encode()))
+                    .await;
+                break;
+            }
+            Err(RecvError::Lagged(_)) => {
+                // Events were dropped, so start over from the whole game. Events are only sent
+                // while the game is locked, so none are missed between the two.
+                let game = locked_game.lock().await;
+                if send_snapshot(&mut writer, &game).await.is_err() {
+                    break;
+                }
+                rx = game.watch();
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if let Err(e) = writer.send(Message::Text(msg.encode())).await {
+            warn!("Failed to send message to websocket: {e}");
+            break;
+        }
+    }
+}
+
+/// Sends everything a spectator needs to show the game as it is now
+async fn send_snapshot(writer: &mut SplitSink<WebSocket, Message>, game: &Game) -> Result<()> {
+    let mut messages = vec![ServerMessage::History(game.history_info()?)];
+    messages.extend(game.clock().map(ServerMessage::Clock));
+    messages.extend(game.result().map(ServerMessage::Result));
+    if game.offers() != Offers::default() {
+        messages.push(ServerMessage::Offers(game.offers()));
+    }
+    for msg in messages {
+        writer.send(Message::Text(msg.encode())).await?;
+    }
+    Ok(())
+}
+
+/// Exchanges protocol versions with the client, failing if they don't match
+pub async fn handshake(ws: &mut WebSocket) -> Result<()> {
+    let hello = ServerMessage::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    ws.send(Message::Text(hello.encode())).await?;
+    loop {
+        let msg = ws
+            .recv()
+            .await
+            .ok_or(anyhow!("WebSocket closed during handshake"))??;
+        let Message::Text(t) = msg else {
+            continue;
+        };
+        let error = match ClientMessage::decode(&t) {
+            Ok(ClientMessage::Hello { version }) if version == PROTOCOL_VERSION => return Ok(()),
+            Ok(ClientMessage::Hello { version }) => format!(
+                "Unsupported protocol version {version}, expected {PROTOCOL_VERSION}"
+            ),
+            _ => "Expected hello".to_owned(),
         };
+        _ = ws
+            .send(Message::Text(ServerMessage::Error(error.clone()).encode()))
+            .await;
+        bail!(error);
     }
 }
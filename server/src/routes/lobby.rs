@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use api::{
+    create::CreateBoard,
+    join::SeatGrant,
+    protocol::WireMessage,
+    rating::{Category, PlayerInfo},
+    seek::{ColorPreference, CreateSeek, LobbyMessage, LobbyRequest},
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use chb_chess::Color;
+use futures::{SinkExt, StreamExt};
+use log::warn;
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    task,
+};
+
+use crate::{
+    config::Settings,
+    game::{rules, ExecExt},
+    lobby::{LobbyEvent, LobbyState},
+    routes::board::{handshake, insert_game, new_game},
+    session::User,
+    storage::Storage,
+    BoardList,
+};
+
+pub async fn join_lobby(
+    wsu: WebSocketUpgrade,
+    State(lobby): State<LobbyState>,
+    State(locked_board_list): State<BoardList>,
+    State(storage): State<Storage>,
+    State(settings): State<Arc<Settings>>,
+    user: User,
+) -> impl IntoResponse {
+    wsu.on_upgrade(move |mut ws: WebSocket| async move {
+        if let Err(e) = handshake(&mut ws).await {
+            warn!("Lobby handshake failed: {e}");
+            return;
+        }
+        let connection = lobby.lock().await.connect();
+        let (matched, matches) = mpsc::unbounded_channel();
+        let seeker = Seeker {
+            lobby,
+            locked_board_list,
+            storage,
+            settings,
+            user,
+            connection,
+            matched,
+        };
+        seeker.run(ws, matches).await;
+        seeker.lobby.lock().await.cancel_all(connection);
+    })
+}
+
+/// Someone connected to the lobby
+struct Seeker {
+    lobby: LobbyState,
+    locked_board_list: BoardList,
+    storage: Storage,
+    settings: Arc<Settings>,
+    user: User,
+    connection: u64,
+    matched: mpsc::UnboundedSender<SeatGrant>,
+}
+
+impl Seeker {
+    /// Keeps the socket up to date with the lobby and handles its requests until it closes
+    async fn run(&self, ws: WebSocket, mut matches: mpsc::UnboundedReceiver<SeatGrant>) {
+        let (mut writer, mut reader) = ws.split();
+        let (seeks, mut rx) = self.lobby.lock().await.watch();
+        let mut next = Some(LobbyMessage::Seeks(seeks));
+        loop {
+            if let Some(msg) = next.take() {
+                if writer.send(Message::Text(msg.encode())).await.is_err() {
+                    break;
+                }
+            }
+            next = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(LobbyEvent::Added(seek)) => Some(LobbyMessage::Added(seek)),
+                    Ok(LobbyEvent::Removed(id)) => Some(LobbyMessage::Removed(id)),
+                    Err(RecvError::Lagged(_)) => {
+                        let (seeks, new_rx) = self.lobby.lock().await.watch();
+                        rx = new_rx;
+                        Some(LobbyMessage::Seeks(seeks))
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Some(grant) = matches.recv() => Some(LobbyMessage::Matched(grant)),
+                msg = reader.next() => match msg {
+                    Some(Ok(Message::Text(t))) => match LobbyRequest::decode(&t) {
+                        Ok(request) => self
+                            .handle(request)
+                            .await
+                            .unwrap_or_else(|e| Some(LobbyMessage::Error(e.to_string()))),
+                        Err(e) => Some(LobbyMessage::Error(format!("Invalid request: {e}"))),
+                    },
+                    Some(Ok(_)) => None,
+                    _ => break,
+                },
+            };
+        }
+    }
+
+    async fn handle(&self, request: LobbyRequest) -> Result<Option<LobbyMessage>> {
+        match request {
+            LobbyRequest::Seek(options) => self.post(options).await.map(Some),
+            LobbyRequest::Cancel(id) => {
+                self.lobby.lock().await.cancel(id, self.connection);
+                Ok(None)
+            }
+            LobbyRequest::Accept(id) => self.accept(id).await.map(Some),
+        }
+    }
+
+    async fn post(&self, options: CreateSeek) -> Result<LobbyMessage> {
+        if options.rated && !self.user.is_registered() {
+            bail!("Only registered users can play rated games");
+        }
+        if options.time_control.is_some_and(|tc| tc.base == 0) {
+            bail!("Invalid time control");
+        }
+        if options.rating_range.is_some_and(|(low, high)| low > high) {
+            bail!("Invalid rating range");
+        }
+        let player = self.player_info(Category::of(options.time_control)).await?;
+        let id = self.lobby.lock().await.add(
+            options,
+            player,
+            self.user.clone(),
+            self.connection,
+            self.matched.clone(),
+        );
+        Ok(LobbyMessage::Posted(id))
+    }
+
+    /// Makes a game from the seek with both players seated, holding the seats until they join
+    async fn accept(&self, id: u64) -> Result<LobbyMessage> {
+        let category = {
+            let lobby = self.lobby.lock().await;
+            let open = lobby
+                .get(id)
+                .ok_or(anyhow!("Seek is no longer open"))?;
+            Category::of(open.seek.options.time_control)
+        };
+        // Read before locking the lobby again, so it isn't held while the database is
+        let rating = self.player_info(category).await?.rating;
+        let open = {
+            let mut lobby = self.lobby.lock().await;
+            let open = lobby
+                .get(id)
+                .ok_or(anyhow!("Seek is no longer open"))?;
+            let options = &open.seek.options;
+            if open.user == self.user {
+                bail!("Can't accept your own seek");
+            }
+            if options.rated && !self.user.is_registered() {
+                bail!("Only registered users can play rated games");
+            }
+            if let Some((low, high)) = options.rating_range {
+                if !rating.is_some_and(|r| (low as f64..=high as f64).contains(&r.rating)) {
+                    bail!("Your rating is outside the seek's range");
+                }
+            }
+            lobby.take(id).expect("Seek was just found")
+        };
+
+        let options = &open.seek.options;
+        let seeker_color = match options.color {
+            ColorPreference::White => Color::White,
+            ColorPreference::Black => Color::Black,
+            ColorPreference::Random if rand::random() => Color::White,
+            ColorPreference::Random => Color::Black,
+        };
+        let accepter_color = rules::opponent(seeker_color);
+        let mut game = new_game(CreateBoard {
+            time_control: options.time_control,
+            rated: options.rated,
+            ..Default::default()
+        })
+        .await
+        .map_err(|_| anyhow!("Failed to create game"))?;
+        let seeker_claim = game.claim_seat(seeker_color, None)?;
+        let accepter_claim = game.claim_seat(accepter_color, None)?;
+        game.set_name(seeker_color, open.user.name());
+        game.set_name(accepter_color, self.user.name());
+
+        let (board_id, game) = insert_game(
+            &self.locked_board_list,
+            self.storage.clone(),
+            &self.settings,
+            game,
+        )
+        .await
+        .map_err(|_| anyhow!("No more games can be started right now"))?;
+        game.clone().hold_seat(seeker_color, seeker_claim.connection);
+        game.hold_seat(accepter_color, accepter_claim.connection);
+        // The seeker may have just left, in which case their seat is given up later
+        _ = open.matched.send(SeatGrant {
+            id: board_id.clone(),
+            color: seeker_color,
+            token: seeker_claim.token,
+        });
+        Ok(LobbyMessage::Matched(SeatGrant {
+            id: board_id,
+            color: accepter_color,
+            token: accepter_claim.token,
+        }))
+    }
+
+    async fn player_info(&self, category: Category) -> Result<PlayerInfo> {
+        let name = self.user.name();
+        let rating = if self.user.is_registered() {
+            let (storage, name) = (self.storage.clone(), name.clone());
+            task::spawn_blocking(move || storage.user_rating(&name, category)).await??
+        } else {
+            None
+        };
+        Ok(PlayerInfo { name, rating })
+    }
+}
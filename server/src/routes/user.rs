@@ -0,0 +1,124 @@
+use api::{
+    rating::UserProfile,
+    user::{Credentials, UserInfo},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::extract::cookie::{Cookie, SignedCookieJar};
+use log::error;
+
+use crate::{
+    code_gen::{get_code, DEFAULT_CODE_LENGTH},
+    session::{self, User},
+    storage::Storage,
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub async fn get_me(user: User) -> Json<UserInfo> {
+    Json(user.info())
+}
+
+/// Ratings and rating history of a registered user
+pub async fn get_user(
+    State(storage): State<Storage>,
+    Path(name): Path<String>,
+) -> Result<Json<UserProfile>, StatusCode> {
+    let profile = || -> anyhow::Result<Option<UserProfile>> {
+        let Some((name, _)) = storage.find_user(&name)? else {
+            return Ok(None);
+        };
+        Ok(Some(UserProfile {
+            ratings: storage.ratings(&name)?,
+            history: storage.rating_history(&name)?,
+            name,
+        }))
+    };
+    match profile() {
+        Ok(Some(profile)) => Ok(Json(profile)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to load user {name}: {e}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn register(
+    State(storage): State<Storage>,
+    jar: SignedCookieJar,
+    Json(Credentials { username, password }): Json<Credentials>,
+) -> Result<(SignedCookieJar, Json<UserInfo>), StatusCode> {
+    if !session::valid_username(&username) || password.len() < MIN_PASSWORD_LENGTH {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Hashing is slow on purpose, so keep it off the async workers
+    let hash = tokio::task::spawn_blocking(move || session::hash_password(&password))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("{e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let created = storage.create_user(&username, &hash).map_err(|e| {
+        error!("Failed to store user {username}: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !created {
+        return Err(StatusCode::CONFLICT);
+    }
+    let cookie = start_session(&storage, &username).await?;
+    let user = User::Registered(username);
+    Ok((jar.add(cookie), Json(user.info())))
+}
+
+pub async fn login(
+    State(storage): State<Storage>,
+    jar: SignedCookieJar,
+    Json(Credentials { username, password }): Json<Credentials>,
+) -> Result<(SignedCookieJar, Json<UserInfo>), StatusCode> {
+    let lookup = storage.clone();
+    let found = tokio::task::spawn_blocking(move || {
+        let found = lookup.find_user(&username)?;
+        // Unknown names still get a password check, so they can't be told apart by timing
+        let hash = found.as_ref().map_or(session::dummy_hash(), |(_, hash)| hash.as_str());
+        let verified = session::verify_password(&password, hash);
+        anyhow::Ok(found.filter(|_| verified).map(|(name, _)| name))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        error!("Failed to look up user: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(name) = found else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let cookie = start_session(&storage, &name).await?;
+    let user = User::Registered(name);
+    Ok((jar.add(cookie), Json(user.info())))
+}
+
+/// Revokes the session and goes back to being a new guest
+pub async fn logout(
+    State(storage): State<Storage>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Json<UserInfo>), StatusCode> {
+    session::end_session(&storage, &jar).await.map_err(|e| {
+        error!("Failed to end session: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let id = get_code(DEFAULT_CODE_LENGTH);
+    let cookie = User::guest_cookie(&id);
+    Ok((jar.add(cookie), Json(User::Guest(id).info())))
+}
+
+async fn start_session(storage: &Storage, name: &str) -> Result<Cookie<'static>, StatusCode> {
+    session::start_session(storage, name).await.map_err(|e| {
+        error!("Failed to start session for {name}: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
@@ -1 +1,192 @@
+use std::sync::OnceLock;
 
+use anyhow::{anyhow, Result};
+use api::user::UserInfo;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, SameSite, SignedCookieJar};
+use log::error;
+use tokio::task;
+
+use crate::{
+    code_gen::{get_code, get_token, DEFAULT_CODE_LENGTH},
+    storage::{self, Storage},
+};
+
+pub const SESSION_COOKIE: &str = "session";
+/// How long a cookie lasts, and a login with it
+const SESSION_DAYS: i64 = 30;
+
+/// The person making a request. Every request has one, see [`ensure_session`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum User {
+    Guest(String),
+    Registered(String),
+}
+
+impl User {
+    pub fn guest() -> Self {
+        Self::Guest(get_code(DEFAULT_CODE_LENGTH))
+    }
+
+    /// Name shown to other players
+    pub fn name(&self) -> String {
+        match self {
+            Self::Guest(id) => format!("Guest {id}"),
+            Self::Registered(name) => name.clone(),
+        }
+    }
+
+    pub fn is_registered(&self) -> bool {
+        matches!(self, Self::Registered(_))
+    }
+
+    pub fn info(&self) -> UserInfo {
+        UserInfo {
+            name: self.name(),
+            registered: self.is_registered(),
+        }
+    }
+
+    /// Cookie naming a guest. Registered users get theirs from [`start_session`].
+    pub fn guest_cookie(id: &str) -> Cookie<'static> {
+        session_cookie(format!("guest:{id}"))
+    }
+
+    /// The user behind a session cookie. Registered users are looked up in `storage`, so their
+    /// sessions can be revoked.
+    async fn from_cookie(storage: &Storage, cookie: &Cookie<'_>) -> Option<Self> {
+        match cookie.value().split_once(':')? {
+            ("guest", id) => Some(Self::Guest(id.to_owned())),
+            ("session", id) => {
+                let (storage, id) = (storage.clone(), id.to_owned());
+                match task::spawn_blocking(move || storage.session_user(&id)).await {
+                    Ok(Ok(name)) => name.map(Self::Registered),
+                    Ok(Err(e)) => {
+                        error!("Failed to look up session: {e}");
+                        None
+                    }
+                    Err(_) => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn session_cookie(value: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, value)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::days(SESSION_DAYS))
+        .finish()
+}
+
+/// Logs `name` in with a new session, returning the cookie that identifies it
+pub async fn start_session(storage: &Storage, name: &str) -> Result<Cookie<'static>> {
+    let id = get_token();
+    let expires_at = storage::now() + SESSION_DAYS * 86_400;
+    let (storage, name, stored_id) = (storage.clone(), name.to_owned(), id.clone());
+    task::spawn_blocking(move || storage.create_session(&stored_id, &name, expires_at)).await??;
+    Ok(session_cookie(format!("session:{id}")))
+}
+
+/// Revokes the session in `jar`, if it is a registered user's
+pub async fn end_session(storage: &Storage, jar: &SignedCookieJar) -> Result<()> {
+    let Some(cookie) = jar.get(SESSION_COOKIE) else {
+        return Ok(());
+    };
+    let Some(id) = cookie.value().strip_prefix("session:") else {
+        return Ok(());
+    };
+    let (storage, id) = (storage.clone(), id.to_owned());
+    task::spawn_blocking(move || storage.revoke_session(&id)).await?
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for User {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<User>()
+            .cloned()
+            // Only happens if a route is missing the middleware
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// Middleware that finds the user behind each request, issuing a new guest identity to anyone
+/// without a valid session cookie
+pub async fn ensure_session<B>(
+    State(storage): State<Storage>,
+    jar: SignedCookieJar,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let existing = match jar.get(SESSION_COOKIE) {
+        Some(cookie) => User::from_cookie(&storage, &cookie).await,
+        None => None,
+    };
+    let user = existing.clone().unwrap_or_else(User::guest);
+    req.extensions_mut().insert(user.clone());
+    let response = next.run(req).await;
+    match user {
+        User::Guest(id) if existing.is_none() && !sets_session(&response) => {
+            (jar.add(User::guest_cookie(&id)), response).into_response()
+        }
+        _ => response,
+    }
+}
+
+/// Whether a handler already replaced the session, like when logging in
+fn sets_session(response: &Response) -> bool {
+    let prefix = format!("{SESSION_COOKIE}=");
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .any(|v| v.to_str().is_ok_and(|v| v.starts_with(&prefix)))
+}
+
+/// Usernames are kept to characters that can't be mistaken for a guest name
+pub fn valid_username(name: &str) -> bool {
+    (3..=20).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {e}"))
+}
+
+/// Hash checked for users that don't exist, so they take as long to turn away as wrong
+/// passwords do
+pub fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("not anyone's password").unwrap_or_default())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::{signal, sync::OwnedMutexGuard};
+
+use crate::{game::Game, storage::Storage, BoardList};
+
+/// Time for connections to pass on the shutdown notice before the server exits
+pub const NOTICE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Completes on Ctrl-C or, on Unix, SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("couldn't listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("couldn't listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+    info!("Shutting down");
+}
+
+/// Pauses every unfinished game and saves what restarting it needs. The games stay locked
+/// until the returned guards are dropped, so none of them can move on after being saved.
+pub async fn suspend_games(boards: &BoardList, storage: &Storage) -> Vec<OwnedMutexGuard<Game>> {
+    let boards = boards.read().await;
+    let mut guards = Vec::with_capacity(boards.len());
+    for (id, live) in boards.iter() {
+        let mut game = live.game.clone().lock_owned().await;
+        if !game.is_finished() {
+            let snapshot = game.suspend();
+            let id = id.clone();
+            storage.queue(move |storage| {
+                if let Err(e) = storage.save_snapshot(&id, &snapshot) {
+                    error!("Failed to save snapshot of game {id}: {e}");
+                }
+            });
+        }
+        guards.push(game);
+    }
+    // Moves are written in the background, so wait for them as well as the snapshots
+    storage.flush().await;
+    info!("Suspended {} games", guards.len());
+    guards
+}
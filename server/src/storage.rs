@@ -0,0 +1,699 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use api::{
+    clock::{ClockState, TimeControl},
+    computer::ComputerSeat,
+    game::{GameResult, Termination},
+    rating::{Category, Rating, RatingChange},
+};
+use chb_chess::{Color, Move};
+use log::error;
+use rand::{thread_rng, Rng};
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+use crate::rating::Glicko;
+
+/// A write made on the storage thread
+type Write = Box<dyn FnOnce(&Storage) + Send>;
+
+/// Handle to the SQLite database holding every game
+#[derive(Clone)]
+pub struct Storage {
+    conn: Arc<Mutex<Connection>>,
+    /// Writes that don't need an answer, made in order on a thread of their own so they don't
+    /// hold up async tasks
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+pub struct StoredGame {
+    pub id: String,
+    pub start_fen: String,
+    pub time_control: Option<TimeControl>,
+    pub names: [Option<String>; 2],
+    pub rated: bool,
+    /// The computer seated when the game was created, if any
+    pub computer: Option<ComputerSeat>,
+    pub moves: Vec<StoredMove>,
+    pub result: Option<GameResult>,
+    /// Unix timestamps in seconds
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// What a game's moves don't say about it, saved when the server shuts down
+pub struct GameSnapshot {
+    /// The paused clocks, including time spent on the move being thought about
+    pub clock: Option<ClockState>,
+    /// Tokens of the players holding each seat
+    pub tokens: [Option<String>; 2],
+}
+
+pub struct StoredMove {
+    pub mv: Move,
+    /// The clocks just after the move was made
+    pub clock: Option<ClockState>,
+    pub played_at: i64,
+}
+
+impl Storage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS games (
+                id TEXT PRIMARY KEY,
+                start_fen TEXT NOT NULL,
+                time_control TEXT,
+                white TEXT,
+                black TEXT,
+                result TEXT,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                rated INTEGER NOT NULL DEFAULT 0,
+                computer TEXT
+            );
+            CREATE TABLE IF NOT EXISTS moves (
+                game_id TEXT NOT NULL REFERENCES games(id),
+                ply INTEGER NOT NULL,
+                mv TEXT NOT NULL,
+                clock TEXT,
+                played_at INTEGER NOT NULL,
+                PRIMARY KEY (game_id, ply)
+            );
+            CREATE TABLE IF NOT EXISTS users (
+                name TEXT PRIMARY KEY COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ratings (
+                user TEXT NOT NULL COLLATE NOCASE,
+                category TEXT NOT NULL,
+                rating REAL NOT NULL,
+                deviation REAL NOT NULL,
+                volatility REAL NOT NULL,
+                games INTEGER NOT NULL,
+                PRIMARY KEY (user, category)
+            );
+            CREATE TABLE IF NOT EXISTS rating_history (
+                user TEXT NOT NULL COLLATE NOCASE,
+                category TEXT NOT NULL,
+                game_id TEXT NOT NULL REFERENCES games(id),
+                rating REAL NOT NULL,
+                deviation REAL NOT NULL,
+                recorded_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS secrets (
+                name TEXT PRIMARY KEY,
+                value BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                user TEXT NOT NULL COLLATE NOCASE REFERENCES users(name),
+                expires_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS snapshots (
+                game_id TEXT PRIMARY KEY REFERENCES games(id),
+                clock TEXT,
+                white_token TEXT,
+                black_token TEXT,
+                taken_at INTEGER NOT NULL
+            );",
+        )?;
+        let (writes, mut queued) = mpsc::unbounded_channel::<Write>();
+        let storage = Self {
+            conn: Arc::new(Mutex::new(conn)),
+            writes,
+        };
+        let writer = storage.clone();
+        thread::Builder::new()
+            .name("storage".to_owned())
+            .spawn(move || {
+                while let Some(write) = queued.blocking_recv() {
+                    write(&writer);
+                }
+            })?;
+        Ok(storage)
+    }
+
+    /// Makes `write` on the storage thread after any writes queued before it
+    pub fn queue(&self, write: impl FnOnce(&Storage) + Send + 'static) {
+        if self.writes.send(Box::new(write)).is_err() {
+            error!("Storage thread has stopped, dropping a write");
+        }
+    }
+
+    /// Waits for every write queued so far
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        self.queue(move |_| {
+            _ = done.send(());
+        });
+        _ = flushed.await;
+    }
+
+    /// Whether `id` is used by a stored game, checked off the async threads
+    pub async fn has_game_blocking(&self, id: String) -> Result<bool> {
+        let storage = self.clone();
+        task::spawn_blocking(move || storage.has_game(&id)).await?
+    }
+
+    pub fn create_game(
+        &self,
+        id: &str,
+        start_fen: &str,
+        time_control: Option<TimeControl>,
+        rated: bool,
+        computer: Option<&ComputerSeat>,
+    ) -> Result<()> {
+        let now = now();
+        self.conn()?.execute(
+            "INSERT INTO games
+            (id, start_fen, time_control, rated, computer, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+            params![id, start_fen, to_json(time_control)?, rated, to_json(computer)?, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_name(&self, id: &str, color: Color, name: &str) -> Result<()> {
+        let sql = match color {
+            Color::White => "UPDATE games SET white = ?2, updated_at = ?3 WHERE id = ?1",
+            Color::Black => "UPDATE games SET black = ?2, updated_at = ?3 WHERE id = ?1",
+        };
+        self.conn()?.execute(sql, params![id, name, now()])?;
+        Ok(())
+    }
+
+    pub fn record_move(
+        &self,
+        id: &str,
+        ply: usize,
+        mv: Move,
+        clock: Option<ClockState>,
+    ) -> Result<()> {
+        let now = now();
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO moves (game_id, ply, mv, clock, played_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, ply as i64, mv.to_string(), to_json(clock)?, now],
+        )?;
+        tx.execute(
+            "UPDATE games SET updated_at = ?2 WHERE id = ?1",
+            params![id, now],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes every move after `ply`
+    pub fn take_back(&self, id: &str, ply: usize) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM moves WHERE game_id = ?1 AND ply > ?2",
+            params![id, ply as i64],
+        )?;
+        tx.execute(
+            "UPDATE games SET updated_at = ?2 WHERE id = ?1",
+            params![id, now()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Saves the result, updating both players' ratings in the same transaction if the game
+    /// is rated
+    pub fn finish_game(&self, id: &str, result: GameResult) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE games SET result = ?2, updated_at = ?3 WHERE id = ?1",
+            params![id, to_json(Some(result))?, now()],
+        )?;
+        rate_game(&tx, id, result)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn has_game(&self, id: &str) -> Result<bool> {
+        let count: i64 = self.conn()?.query_row(
+            "SELECT COUNT(*) FROM games WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    pub fn load_game(&self, id: &str) -> Result<Option<StoredGame>> {
+        let conn = self.conn()?;
+        let game = conn
+            .query_row(
+                "SELECT id, start_fen, time_control, white, black, result, created_at, updated_at,
+                rated, computer FROM games WHERE id = ?1",
+                params![id],
+                read_game,
+            )
+            .optional()?;
+        match game {
+            Some(game) => Ok(Some(with_moves(&conn, game?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every game that has not finished yet
+    pub fn unfinished_games(&self) -> Result<Vec<StoredGame>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, start_fen, time_control, white, black, result, created_at, updated_at,
+            rated, computer FROM games WHERE result IS NULL",
+        )?;
+        let games = stmt
+            .query_map([], read_game)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        games
+            .into_iter()
+            .map(|game| with_moves(&conn, game?))
+            .collect()
+    }
+
+    /// Adds a user, returning false if the name is taken
+    pub fn create_user(&self, name: &str, password_hash: &str) -> Result<bool> {
+        let inserted = self.conn()?.execute(
+            "INSERT OR IGNORE INTO users (name, password_hash, created_at) VALUES (?1, ?2, ?3)",
+            params![name, password_hash, now()],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// The user's name as registered, which may differ in case from `name`, and their password
+    /// hash
+    pub fn find_user(&self, name: &str) -> Result<Option<(String, String)>> {
+        self.conn()?
+            .query_row(
+                "SELECT name, password_hash FROM users WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Starts session `id` for `user`, clearing out any that have expired
+    pub fn create_session(&self, id: &str, user: &str, expires_at: i64) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now()])?;
+        conn.execute(
+            "INSERT INTO sessions (id, user, expires_at) VALUES (?1, ?2, ?3)",
+            params![id, user, expires_at],
+        )?;
+        Ok(())
+    }
+
+    /// The user session `id` belongs to, unless it has expired or been revoked
+    pub fn session_user(&self, id: &str) -> Result<Option<String>> {
+        self.conn()?
+            .query_row(
+                "SELECT user FROM sessions WHERE id = ?1 AND expires_at > ?2",
+                params![id, now()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn revoke_session(&self, id: &str) -> Result<()> {
+        self.conn()?.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// The user's rating in `category`, or `None` if there is no such user
+    pub fn user_rating(&self, name: &str, category: Category) -> Result<Option<Rating>> {
+        if self.find_user(name)?.is_none() {
+            return Ok(None);
+        }
+        let (glicko, games) = load_rating(&self.conn()?, name, category)?;
+        Ok(Some(glicko.info(category, games)))
+    }
+
+    /// Every category the user has played rated games in
+    pub fn ratings(&self, name: &str) -> Result<Vec<Rating>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT category, rating, deviation, volatility, games FROM ratings WHERE user = ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![name], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Glicko {
+                        rating: row.get(1)?,
+                        deviation: row.get(2)?,
+                        volatility: row.get(3)?,
+                    },
+                    row.get::<_, u32>(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(category, glicko, games)| Ok(glicko.info(parse_category(&category)?, games)))
+            .collect()
+    }
+
+    pub fn rating_history(&self, name: &str) -> Result<Vec<RatingChange>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT category, game_id, rating, deviation, recorded_at FROM rating_history
+            WHERE user = ?1 ORDER BY recorded_at, rowid",
+        )?;
+        let rows = stmt
+            .query_map(params![name], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(category, game_id, rating, deviation, recorded_at)| {
+                Ok(RatingChange {
+                    category: parse_category(&category)?,
+                    game_id,
+                    rating,
+                    deviation,
+                    recorded_at,
+                })
+            })
+            .collect()
+    }
+
+    pub fn save_snapshot(&self, id: &str, snapshot: &GameSnapshot) -> Result<()> {
+        let [white, black] = &snapshot.tokens;
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO snapshots (game_id, clock, white_token, black_token, taken_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, to_json(snapshot.clock)?, white, black, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Every saved snapshot by game id, removing them so they are only restored once
+    pub fn take_snapshots(&self) -> Result<HashMap<String, GameSnapshot>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let snapshots = {
+            let mut stmt =
+                tx.prepare("SELECT game_id, clock, white_token, black_token FROM snapshots")?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        [row.get(2)?, row.get(3)?],
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            rows.into_iter()
+                .map(|(id, clock, tokens)| {
+                    let snapshot = GameSnapshot {
+                        clock: from_json(clock)?,
+                        tokens,
+                    };
+                    Ok((id, snapshot))
+                })
+                .collect::<Result<HashMap<_, _>>>()?
+        };
+        tx.execute("DELETE FROM snapshots", [])?;
+        tx.commit()?;
+        Ok(snapshots)
+    }
+
+    /// Key for signing cookies, generated the first time it is needed so sessions survive
+    /// restarts
+    pub fn cookie_key(&self) -> Result<Vec<u8>> {
+        let conn = self.conn()?;
+        let key = conn
+            .query_row(
+                "SELECT value FROM secrets WHERE name = 'cookie_key'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(key) = key {
+            return Ok(key);
+        }
+        let mut key = vec![0u8; 64];
+        thread_rng().fill(&mut key[..]);
+        conn.execute(
+            "INSERT INTO secrets (name, value) VALUES ('cookie_key', ?1)",
+            params![key],
+        )?;
+        Ok(key)
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| anyhow!("Storage connection poisoned"))
+    }
+}
+
+/// Storage for a single game, which queues its writes and logs failures instead of interrupting
+/// play
+pub struct GameRecord {
+    storage: Storage,
+    id: String,
+}
+
+impl GameRecord {
+    pub fn new(storage: Storage, id: String) -> Self {
+        Self { storage, id }
+    }
+
+    pub fn create(
+        &self,
+        start_fen: &str,
+        time_control: Option<TimeControl>,
+        rated: bool,
+        computer: Option<&ComputerSeat>,
+    ) {
+        let (start_fen, computer) = (start_fen.to_owned(), computer.cloned());
+        self.write(move |storage, id| {
+            let computer = computer.as_ref();
+            if let Err(e) = storage.create_game(id, &start_fen, time_control, rated, computer) {
+                error!("Failed to store game {id}: {e}");
+            }
+        });
+    }
+
+    pub fn set_name(&self, color: Color, name: &str) {
+        let name = name.to_owned();
+        self.write(move |storage, id| {
+            if let Err(e) = storage.set_name(id, color, &name) {
+                error!("Failed to store player for game {id}: {e}");
+            }
+        });
+    }
+
+    pub fn record_move(&self, ply: usize, mv: Move, clock: Option<ClockState>) {
+        self.write(move |storage, id| {
+            if let Err(e) = storage.record_move(id, ply, mv, clock) {
+                error!("Failed to store move for game {id}: {e}");
+            }
+        });
+    }
+
+    pub fn take_back(&self, ply: usize) {
+        self.write(move |storage, id| {
+            if let Err(e) = storage.take_back(id, ply) {
+                error!("Failed to store takeback for game {id}: {e}");
+            }
+        });
+    }
+
+    pub fn finish(&self, result: GameResult) {
+        self.write(move |storage, id| {
+            if let Err(e) = storage.finish_game(id, result) {
+                error!("Failed to store result for game {id}: {e}");
+            }
+        });
+    }
+
+    fn write(&self, write: impl FnOnce(&Storage, &str) + Send + 'static) {
+        let id = self.id.clone();
+        self.storage.queue(move |storage| write(storage, &id));
+    }
+}
+
+fn read_game(row: &rusqlite::Row) -> rusqlite::Result<Result<StoredGame>> {
+    let time_control: Option<String> = row.get(2)?;
+    let result: Option<String> = row.get(5)?;
+    let computer: Option<String> = row.get(9)?;
+    let game = || -> Result<StoredGame> {
+        Ok(StoredGame {
+            id: row.get(0)?,
+            start_fen: row.get(1)?,
+            time_control: from_json(time_control)?,
+            names: [row.get(3)?, row.get(4)?],
+            rated: row.get(8)?,
+            computer: from_json(computer)?,
+            moves: Vec::new(),
+            result: from_json(result)?,
+            created_at: row.get(6)?,
+            updated_at: row.get(7)?,
+        })
+    };
+    Ok(game())
+}
+
+/// Updates the ratings of both players if the game is rated and both seats were taken
+fn rate_game(conn: &Connection, id: &str, result: GameResult) -> Result<()> {
+    if result.reason == Termination::Aborted {
+        return Ok(());
+    }
+    let (rated, time_control, white, black): (bool, _, Option<String>, Option<String>) = conn
+        .query_row(
+            "SELECT rated, time_control, white, black FROM games WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+    let (true, Some(white), Some(black)) = (rated, white, black) else {
+        return Ok(());
+    };
+    let category = Category::of(from_json(time_control)?);
+    let white_score = match result.winner {
+        Some(Color::White) => 1.0,
+        Some(Color::Black) => 0.0,
+        None => 0.5,
+    };
+    let (white_rating, white_games) = load_rating(conn, &white, category)?;
+    let (black_rating, black_games) = load_rating(conn, &black, category)?;
+    let updates = [
+        (
+            &white,
+            white_rating.update(black_rating, white_score),
+            white_games,
+        ),
+        (
+            &black,
+            black_rating.update(white_rating, 1.0 - white_score),
+            black_games,
+        ),
+    ];
+    let now = now();
+    for (name, glicko, games) in updates {
+        conn.execute(
+            "INSERT INTO ratings (user, category, rating, deviation, volatility, games)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (user, category) DO UPDATE SET
+                rating = ?3, deviation = ?4, volatility = ?5, games = ?6",
+            params![
+                name,
+                category.to_string(),
+                glicko.rating,
+                glicko.deviation,
+                glicko.volatility,
+                games + 1
+            ],
+        )?;
+        conn.execute(
+            "INSERT INTO rating_history (user, category, game_id, rating, deviation, recorded_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                name,
+                category.to_string(),
+                id,
+                glicko.rating,
+                glicko.deviation,
+                now
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// The user's rating and number of rated games, starting from the default for new players
+fn load_rating(conn: &Connection, name: &str, category: Category) -> Result<(Glicko, u32)> {
+    let rating = conn
+        .query_row(
+            "SELECT rating, deviation, volatility, games FROM ratings
+            WHERE user = ?1 AND category = ?2",
+            params![name, category.to_string()],
+            |row| {
+                Ok((
+                    Glicko {
+                        rating: row.get(0)?,
+                        deviation: row.get(1)?,
+                        volatility: row.get(2)?,
+                    },
+                    row.get(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(rating.unwrap_or_default())
+}
+
+fn parse_category(category: &str) -> Result<Category> {
+    category.parse().map_err(|e: String| anyhow!(e))
+}
+
+fn with_moves(conn: &Connection, mut game: StoredGame) -> Result<StoredGame> {
+    let mut stmt =
+        conn.prepare("SELECT mv, clock, played_at FROM moves WHERE game_id = ?1 ORDER BY ply")?;
+    let rows = stmt
+        .query_map(params![game.id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (mv, clock, played_at) in rows {
+        game.moves.push(StoredMove {
+            mv: mv
+                .parse()
+                .map_err(|_| anyhow!("Stored move {mv} is invalid"))?,
+            clock: from_json(clock)?,
+            played_at,
+        });
+    }
+    Ok(game)
+}
+
+/// Serializes optional columns, leaving `None` as NULL
+fn to_json<T: serde::Serialize>(value: Option<T>) -> Result<Option<String>> {
+    value
+        .map(|v| serde_json::to_string(&v))
+        .transpose()
+        .map_err(Into::into)
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(value: Option<String>) -> Result<Option<T>> {
+    value
+        .map(|v| serde_json::from_str(&v))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Current Unix timestamp in seconds
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}